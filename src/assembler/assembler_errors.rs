@@ -1,10 +1,156 @@
+use std::fmt;
+use std::iter;
+
+/// Where in the source an error was found. Lines and columns start at 1; a
+/// line of 0 means the location is unknown.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceSpan {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourceSpan {
+    /// Converts a byte offset into `source` into a line and column.
+    pub fn from_offset(file: &str, source: &str, offset: usize) -> SourceSpan {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        SourceSpan {
+            file: file.to_string(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum AssemblerError {
-    NoSegmentDeclarationFound { instruction: u32 },
-    StringConstantDeclaredWithoutLabel { instruction: u32 },
-    SymbolAlreadyDeclared,
-    UnknownDirectiveFound { directive: String },
-    NonOpcodeInOpcodeField,
-    InsufficientSections,
-    ParseError { error: String },
+    NoSegmentDeclarationFound { span: SourceSpan },
+    StringConstantDeclaredWithoutLabel { span: SourceSpan },
+    SymbolAlreadyDeclared { name: String, span: SourceSpan },
+    UnknownDirectiveFound { directive: String, span: SourceSpan },
+    NonOpcodeInOpcodeField { span: SourceSpan },
+    InvalidOperand { span: SourceSpan },
+    OperandMismatch { expected: String, found: String, span: SourceSpan },
+    InsufficientSections { span: SourceSpan },
+    UnparsedInput { text: String, span: SourceSpan },
+    UndefinedSymbol { name: String, span: SourceSpan },
+    /// `.entry` names a symbol that is not a label in `.code`.
+    EntryNotCodeLabel { name: String, span: SourceSpan },
+    /// An integer literal outside `min..=max`, the values its field takes.
+    LiteralOutOfRange { value: i64, bits: u8, min: i64, max: i64, span: SourceSpan },
+    /// A label or constant lies beyond what a 16-bit operand can address.
+    OffsetOutOfRange { operand: String, offset: u32, span: SourceSpan },
+    MissingFloatConstant { value: f64, span: SourceSpan },
+}
+
+impl AssemblerError {
+    pub fn span(&self) -> &SourceSpan {
+        match self {
+            AssemblerError::NoSegmentDeclarationFound { span } |
+            AssemblerError::StringConstantDeclaredWithoutLabel { span } |
+            AssemblerError::SymbolAlreadyDeclared { span, .. } |
+            AssemblerError::UnknownDirectiveFound { span, .. } |
+            AssemblerError::NonOpcodeInOpcodeField { span } |
+            AssemblerError::InvalidOperand { span } |
+            AssemblerError::OperandMismatch { span, .. } |
+            AssemblerError::InsufficientSections { span } |
+            AssemblerError::UnparsedInput { span, .. } |
+            AssemblerError::UndefinedSymbol { span, .. } |
+            AssemblerError::EntryNotCodeLabel { span, .. } |
+            AssemblerError::LiteralOutOfRange { span, .. } |
+            AssemblerError::OffsetOutOfRange { span, .. } |
+            AssemblerError::MissingFloatConstant { span, .. } => span,
+        }
+    }
+
+    /// Formats the error the way rustc does, quoting the offending line of
+    /// `source` with a caret under the column the span points at.
+    pub fn render(&self, source: &str) -> String {
+        let span = self.span();
+        let mut out = format!("error: {}\n", self);
+        if span.line == 0 {
+            return out;
+        }
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        let text = source.lines().nth(span.line - 1).unwrap_or("");
+        out.push_str(&format!("{}--> {}\n", gutter, span));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", span.line, text));
+        // Tabs are kept so that the caret lines up with the quoted line.
+        let padding: String = text.chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .chain(iter::repeat(' '))
+            .take(span.column - 1)
+            .collect();
+        out.push_str(&format!("{} | {}^\n", gutter, padding));
+        out
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerError::NoSegmentDeclarationFound { .. } => write!(f, "label declared before any .data or .code section"),
+            AssemblerError::StringConstantDeclaredWithoutLabel { .. } => write!(f, "string constant declared without a label"),
+            AssemblerError::SymbolAlreadyDeclared { name, .. } => write!(f, "symbol `{}` is already declared", name),
+            AssemblerError::UnknownDirectiveFound { directive, .. } => write!(f, "unknown directive `.{}`", directive),
+            AssemblerError::NonOpcodeInOpcodeField { .. } => write!(f, "expected an opcode"),
+            AssemblerError::InvalidOperand { .. } => write!(f, "invalid operand"),
+            AssemblerError::OperandMismatch { expected, found, .. } => write!(f, "expected operands `{}`, found `{}`", expected, found),
+            AssemblerError::InsufficientSections { .. } => write!(f, "a program needs both a .data and a .code section"),
+            AssemblerError::UnparsedInput { text, .. } => write!(f, "unable to parse `{}`", text),
+            AssemblerError::UndefinedSymbol { name, .. } => write!(f, "undefined symbol `{}`", name),
+            AssemblerError::EntryNotCodeLabel { name, .. } => write!(f, "entry point `{}` is not a label in the code section", name),
+            AssemblerError::LiteralOutOfRange { value, bits, min, max, .. } => write!(f, "literal {} does not fit in a {}-bit field, which takes {}..={}", value, bits, min, max),
+            AssemblerError::OffsetOutOfRange { operand, offset, .. } => write!(f, "`{}` is at offset {}, which does not fit in 16 bits", operand, offset),
+            AssemblerError::MissingFloatConstant { value, .. } => write!(f, "internal error: float constant {:?} was not placed in the read-only section", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_from_offset() {
+        let source = ".data\n.code\n  hlt\n";
+        let span = SourceSpan::from_offset("test.iasm", source, 14);
+        assert_eq!(span, SourceSpan { file: "test.iasm".to_string(), line: 3, column: 3 });
+        assert_eq!(span.to_string(), "test.iasm:3:3");
+    }
+
+    #[test]
+    fn test_render() {
+        let source = ".data\n.code\n  jmpe @nowhere\n";
+        let error = AssemblerError::UndefinedSymbol {
+            name: "nowhere".to_string(),
+            span: SourceSpan::from_offset("test.iasm", source, 14),
+        };
+        assert_eq!(
+            error.render(source),
+            "error: undefined symbol `nowhere`\n --> test.iasm:3:3\n  |\n3 |   jmpe @nowhere\n  |   ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_tab_indented() {
+        let source = ".data\n.code\n\tload\t$0 @nowhere\n";
+        let error = AssemblerError::UndefinedSymbol {
+            name: "nowhere".to_string(),
+            span: SourceSpan::from_offset("test.iasm", source, 21),
+        };
+        assert_eq!(
+            error.render(source),
+            "error: undefined symbol `nowhere`\n --> test.iasm:3:10\n  |\n3 | \tload\t$0 @nowhere\n  | \t    \t   ^\n"
+        );
+    }
 }
@@ -0,0 +1,117 @@
+use nom::IResult;
+use nom::multispace0;
+use nom::types::CompleteStr;
+
+/// Like nom's `ws!`, but also skips comments wherever whitespace is allowed.
+macro_rules! ws_comments (
+    ($i:expr, $($args:tt)*) => (
+        {
+            use nom::Convert;
+            use nom::Err;
+
+            match sep!($i, ::assembler::comment_parsers::trivia, $($args)*) {
+                Err(e) => Err(e),
+                Ok((i1, o)) => {
+                    match ::assembler::comment_parsers::trivia(i1) {
+                        Err(e) => Err(Err::convert(e)),
+                        Ok((i2, _)) => Ok((i2, o)),
+                    }
+                }
+            }
+        }
+    )
+);
+
+// `; ...` runs to the end of the line. The newline is left for the caller.
+named!(line_comment<CompleteStr, CompleteStr>,
+    recognize!(
+        do_parse!(
+            tag!(";") >>
+            take_till!(|c| c == '\n') >>
+            ()
+        )
+    )
+);
+
+named!(block_comment<CompleteStr, CompleteStr>,
+    recognize!(
+        do_parse!(
+            tag!("/*") >>
+            take_until!("*/") >>
+            tag!("*/") >>
+            ()
+        )
+    )
+);
+
+named!(pub comment<CompleteStr, CompleteStr>,
+    alt!(line_comment | block_comment)
+);
+
+/// Consumes any mix of whitespace and comments, returning what it consumed.
+/// Never fails; an unterminated block comment is left in the input.
+pub fn trivia(input: CompleteStr) -> IResult<CompleteStr, CompleteStr> {
+    let mut rest = input;
+    loop {
+        if let Ok((remaining, _)) = multispace0(rest) {
+            rest = remaining;
+        }
+        match comment(rest) {
+            Ok((remaining, _)) => rest = remaining,
+            Err(_) => break,
+        }
+    }
+    let consumed = input.len() - rest.len();
+    Ok((rest, CompleteStr(&input[..consumed])))
+}
+
+/// Finds the comments in a piece of source that has already been parsed,
+/// returning the byte offset and text of each. Quoted strings are skipped so
+/// that `.asciiz 'a;b'` is not mistaken for a comment.
+pub fn find_comments(source: &str) -> Vec<(usize, &str)> {
+    let mut comments = vec![];
+    let mut offset = 0;
+    while offset < source.len() {
+        let rest = &source[offset..];
+        if let Some(quoted) = rest.strip_prefix('\'') {
+            offset += quoted.find('\'').map(|end| end + 2).unwrap_or_else(|| rest.len());
+            continue;
+        }
+        if let Ok((remaining, text)) = comment(CompleteStr(rest)) {
+            comments.push((offset, &text[..]));
+            offset += rest.len() - remaining.len();
+            continue;
+        }
+        offset += rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+    }
+    comments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comment() {
+        let result = comment(CompleteStr("; a comment\nhlt"));
+        assert_eq!(result, Ok((CompleteStr("\nhlt"), CompleteStr("; a comment"))));
+        let result = comment(CompleteStr("/* one\ntwo */hlt"));
+        assert_eq!(result, Ok((CompleteStr("hlt"), CompleteStr("/* one\ntwo */"))));
+        let result = comment(CompleteStr("/* unterminated"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_trivia() {
+        let result = trivia(CompleteStr("  ; one\n\t/* two */ \nhlt"));
+        assert_eq!(result, Ok((CompleteStr("hlt"), CompleteStr("  ; one\n\t/* two */ \n"))));
+        let result = trivia(CompleteStr("hlt"));
+        assert_eq!(result, Ok((CompleteStr("hlt"), CompleteStr(""))));
+    }
+
+    #[test]
+    fn test_find_comments() {
+        let source = "hello: .asciiz 'a;b' ; greeting\nload /* reg */ $0 #1";
+        assert_eq!(find_comments(source), vec![(21, "; greeting"), (37, "/* reg */")]);
+    }
+}
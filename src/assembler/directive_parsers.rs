@@ -1,7 +1,8 @@
-use nom::alpha1;
+use nom::{alpha1, alphanumeric1};
 use nom::types::CompleteStr;
 
 use assembler::Token;
+use assembler::assembler_errors::SourceSpan;
 use assembler::instruction_parsers::AssemblerInstruction;
 use assembler::label_parsers::label_declaration;
 use assembler::operand_parsers::operand;
@@ -17,7 +18,7 @@ named!(directive_declaration<CompleteStr, Token>,
 );
 
 named!(directive_combined<CompleteStr, AssemblerInstruction>,
-    ws!(
+    ws_comments!(
         do_parse!(
             l: opt!(label_declaration) >>
             name: directive_declaration >>
@@ -32,6 +33,36 @@ named!(directive_combined<CompleteStr, AssemblerInstruction>,
                     operand1: o1,
                     operand2: o2,
                     operand3: o3,
+                    span: SourceSpan::default(),
+                }
+            )
+        )
+    )
+);
+
+// The keyword is its own parser so that `ws_comments!` cannot skip into it;
+// `.entrypoint` is some other directive, not `.entry point`.
+named!(entry_keyword<CompleteStr, CompleteStr>,
+    terminated!(tag!(".entry"), not!(alphanumeric1))
+);
+
+// `.entry main` names the label execution starts at. The `@` is optional
+// here because the operand can only ever be a label.
+named!(entry_directive<CompleteStr, AssemblerInstruction>,
+    ws_comments!(
+        do_parse!(
+            entry_keyword >>
+            opt!(tag!("@")) >>
+            name: alphanumeric1 >>
+            (
+                AssemblerInstruction{
+                    opcode: None,
+                    directive: Some(Token::Directive{name: "entry".to_string()}),
+                    label: None,
+                    operand1: Some(Token::LabelUsage{name: name.to_string()}),
+                    operand2: None,
+                    operand3: None,
+                    span: SourceSpan::default(),
                 }
             )
         )
@@ -41,6 +72,7 @@ named!(directive_combined<CompleteStr, AssemblerInstruction>,
 named!(pub directive<CompleteStr, AssemblerInstruction>,
     do_parse!(
         ins: alt!(
+            entry_directive |
             directive_combined
         ) >>
         (
@@ -51,6 +83,7 @@ named!(pub directive<CompleteStr, AssemblerInstruction>,
 
 mod tests {
     #![allow(unused_imports)]
+    #![allow(clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -67,6 +100,7 @@ mod tests {
                 operand1: None,
                 operand2: None,
                 operand3: None,
+                span: SourceSpan::default(),
             }
         );
     }
@@ -85,8 +119,28 @@ mod tests {
                 operand1: Some(Token::IrString { name: "Hello".to_string() }),
                 operand2: None,
                 operand3: None,
+                span: SourceSpan::default(),
             },
             directive,
         );
     }
+
+    #[test]
+    fn test_entry_directive() {
+        let expected = AssemblerInstruction {
+            opcode: None,
+            label: None,
+            directive: Some(Token::Directive { name: "entry".to_string() }),
+            operand1: Some(Token::LabelUsage { name: "main".to_string() }),
+            operand2: None,
+            operand3: None,
+            span: SourceSpan::default(),
+        };
+        for source in &[".entry main\n", ".entry @main", "  .entry /* start */ main ; here\n"] {
+            let (leftover, instruction) = directive(CompleteStr(source)).unwrap();
+            assert_eq!(leftover, CompleteStr(""));
+            assert_eq!(instruction, expected);
+        }
+        assert!(entry_directive(CompleteStr(".entrymain")).is_err());
+    }
 }
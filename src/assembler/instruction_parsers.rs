@@ -2,11 +2,13 @@ use nom::multispace;
 use nom::types::CompleteStr;
 
 use assembler::SymbolTable;
+use assembler::assembler_errors::{AssemblerError, SourceSpan};
 use assembler::Token;
 use assembler::opcode_parsers::opcode;
 use assembler::operand_parsers::operand;
 use assembler::register_parsers::register;
 use assembler::label_parsers::label_declaration;
+use instruction::{Opcode, OperandKind, NO_REGISTER};
 
 #[derive(Debug, PartialEq)]
 pub struct AssemblerInstruction {
@@ -16,6 +18,7 @@ pub struct AssemblerInstruction {
     pub operand1: Option<Token>,
     pub operand2: Option<Token>,
     pub operand3: Option<Token>,
+    pub span: SourceSpan,
 }
 
 named!(pub instruction<CompleteStr, AssemblerInstruction>,
@@ -30,7 +33,7 @@ named!(pub instruction<CompleteStr, AssemblerInstruction>,
 );
 
 named!(instruction_combined<CompleteStr, AssemblerInstruction>,
-    ws!(
+    ws_comments!(
         do_parse!(
             l: opt!(label_declaration) >>
             o: opcode >>
@@ -45,6 +48,7 @@ named!(instruction_combined<CompleteStr, AssemblerInstruction>,
                     operand1: o1,
                     operand2: o2,
                     operand3: o3,
+                    span: SourceSpan::default(),
                 }
             )
         )
@@ -63,6 +67,7 @@ named!(instruction_one<CompleteStr, AssemblerInstruction>,
                 operand1: None,
                 operand2: None,
                 operand3: None,
+                span: SourceSpan::default(),
             }
         )
     )
@@ -81,31 +86,74 @@ named!(instruction_two<CompleteStr, AssemblerInstruction>,
                 operand1: Some(r),
                 operand2: Some(i),
                 operand3: None,
+                span: SourceSpan::default(),
             }
         )
     )
 );
 
+fn operand_matches(kind: OperandKind, token: &Token) -> bool {
+    matches!(
+        (kind, token),
+        (OperandKind::Register, Token::Register { .. }) |
+        (OperandKind::OptionalRegister, Token::Register { .. }) |
+        (OperandKind::Integer8, Token::IntegerOperand { .. }) |
+        (OperandKind::Integer16, Token::IntegerOperand { .. }) |
+        (OperandKind::Address, Token::IntegerOperand { .. }) |
+        (OperandKind::Address, Token::LabelUsage { .. }) |
+        (OperandKind::Float, Token::FloatOperand { .. })
+    )
+}
+
+fn token_kind_name(token: &Token) -> &'static str {
+    match token {
+        Token::Register { .. } => "register",
+        Token::IntegerOperand { .. } => "integer",
+        Token::FloatOperand { .. } => "float",
+        Token::LabelUsage { .. } => "label",
+        Token::IrString { .. } => "string",
+        _ => "opcode",
+    }
+}
+
+fn describe<T, F: Fn(&T) -> String>(items: &[T], name: F) -> String {
+    if items.is_empty() {
+        return "no operands".to_string();
+    }
+    items.iter().map(name).collect::<Vec<String>>().join(", ")
+}
+
+// The values a literal may take in a field. The VM zero-extends every
+// immediate, so negative literals are rejected rather than silently loaded
+// as large positive numbers. `loadhi` is the exception: it sets the high
+// half of a register, where a negative literal is the sign of the result.
+fn literal_range(code: Opcode, kind: OperandKind) -> (u8, i64, i64) {
+    match kind {
+        OperandKind::Integer8 => (8, 0, 0xff),
+        _ if code == Opcode::LOADHI => (16, -0x8000, 0xffff),
+        _ => (16, 0, 0xffff),
+    }
+}
+
 impl AssemblerInstruction {
-    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
-        let mut results = vec![];
-        let ref token = self.opcode;
-        match token {
-            Some(Token::Op { code }) => match code {
-                _ => {
-                    let b: u8 = (*code).into();
-                    results.push(b);
-                }
-            },
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, AssemblerError> {
+        let code = match self.opcode {
+            Some(Token::Op { code }) if code != Opcode::IGL => code,
             _ => {
-                println!("Non-opcode found in opcode field");
-                std::process::exit(1);
+                return Err(AssemblerError::NonOpcodeInOpcodeField { span: self.span.clone() });
             },
-        }
+        };
+        let signature = code.signature();
+        let operands = self.operands();
+        self.check_operands(signature, &operands)?;
 
-        for operand in &[&self.operand1, &self.operand2, &self.operand3] {
-            if let Some(token) = operand {
-                AssemblerInstruction::extract_operand(token, &mut results, symbols);
+        let mut results = vec![code.into()];
+        for (token, kind) in operands.iter().zip(signature) {
+            self.extract_operand(token, code, *kind, &mut results, symbols)?;
+        }
+        for kind in &signature[operands.len()..] {
+            if *kind == OperandKind::OptionalRegister {
+                results.push(NO_REGISTER);
             }
         }
 
@@ -113,7 +161,27 @@ impl AssemblerInstruction {
             results.push(0);
         }
 
-        results
+        Ok(results)
+    }
+
+    fn operands(&self) -> Vec<&Token> {
+        [&self.operand1, &self.operand2, &self.operand3].iter().copied().flatten().collect()
+    }
+
+    // Every operand has to match its slot, and only trailing optional
+    // registers may be left out.
+    fn check_operands(&self, signature: &[OperandKind], operands: &[&Token]) -> Result<(), AssemblerError> {
+        let required = signature.iter().filter(|k| **k != OperandKind::OptionalRegister).count();
+        let matches = operands.len() >= required && operands.len() <= signature.len() &&
+            operands.iter().zip(signature).all(|(token, kind)| operand_matches(*kind, token));
+        if matches {
+            return Ok(());
+        }
+        Err(AssemblerError::OperandMismatch {
+            expected: describe(signature, |k| k.to_string()),
+            found: describe(operands, |t| token_kind_name(t).to_string()),
+            span: self.span.clone(),
+        })
     }
 
     pub fn has_operands(&self) -> bool {
@@ -134,52 +202,96 @@ impl AssemblerInstruction {
 
     pub fn get_directive_name(&self) -> Option<String> {
         match &self.directive {
-            Some(d) => match d {
-                Token::Directive { name } => Some(name.to_string()),
-                _ => None,
-            },
-            None => None,
+            Some(Token::Directive { name }) => Some(name.to_string()),
+            _ => None,
         }
     }
 
     pub fn get_string_constant(&self) -> Option<String> {
         match &self.operand1 {
-            Some(d) => match d {
-                Token::IrString { name } => Some(name.to_string()),
-                _ => None,
-            },
-            None => None,
+            Some(Token::IrString { name }) => Some(name.to_string()),
+            _ => None,
         }
     }
 
     pub fn get_label_name(&self) -> Option<String> {
         match &self.label {
-            Some(l) => match l {
-                Token::LabelDeclaration { name } => Some(name.clone()),
-                _ => None,
-            },
-            None => None,
+            Some(Token::LabelDeclaration { name }) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_float_constants(&self) -> Vec<f64> {
+        let mut values = vec![];
+        for token in [&self.operand1, &self.operand2, &self.operand3].iter().copied().flatten() {
+            if let Token::FloatOperand { value } = token {
+                values.push(*value);
+            }
         }
+        values
     }
 
-    fn extract_operand(t: &Token, results: &mut Vec<u8>, _symbols: &SymbolTable) {
+    pub fn get_label_usages(&self) -> Vec<String> {
+        let mut names = vec![];
+        for token in [&self.operand1, &self.operand2, &self.operand3].iter().copied().flatten() {
+            if let Token::LabelUsage { name } = token {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    fn extract_operand(&self, t: &Token, code: Opcode, kind: OperandKind, results: &mut Vec<u8>, symbols: &SymbolTable) -> Result<(), AssemblerError> {
         match t {
             Token::Register { reg_num } => {
                 results.push(*reg_num);
             }
             Token::IntegerOperand { value } => {
-                let converted = *value as u16;
-                let byte1 = converted;
-                let byte2 = converted >> 8;
-                results.push(byte2 as u8);
-                results.push(byte1 as u8);
+                let (bits, min, max) = literal_range(code, kind);
+                if !(min..=max).contains(value) {
+                    return Err(AssemblerError::LiteralOutOfRange { value: *value, bits, min, max, span: self.span.clone() });
+                }
+                if bits == 8 {
+                    results.push(*value as u8);
+                } else {
+                    let converted = *value as u16;
+                    results.push((converted >> 8) as u8);
+                    results.push(converted as u8);
+                }
+            }
+            // Floats do not fit in an instruction, so the operand is the
+            // offset of the constant in the read-only section.
+            Token::FloatOperand { value } => {
+                // The first pass places every constant, so a missing one is
+                // a bug in the assembler rather than in the source.
+                let offset = match symbols.float_constant_offset(*value) {
+                    Some(offset) => offset,
+                    None => return Err(AssemblerError::MissingFloatConstant { value: *value, span: self.span.clone() }),
+                };
+                self.push_offset(format!("#{:?}", value), offset, results)?;
+            }
+            Token::LabelUsage { name } => {
+                let value = match symbols.symbol_value(name) {
+                    Some(value) => value,
+                    None => return Err(AssemblerError::UndefinedSymbol { name: name.clone(), span: self.span.clone() }),
+                };
+                self.push_offset(format!("@{}", name), value, results)?;
             }
-            Token::LabelUsage { name: _ } => {}
             _ => {
-                println!("Opcode found in operand field");
-                std::process::exit(1);
+                return Err(AssemblerError::InvalidOperand { span: self.span.clone() });
             }
         };
+        Ok(())
+    }
+
+    // Image offsets are u32, but an operand only has room for 16 bits.
+    fn push_offset(&self, operand: String, offset: u32, results: &mut Vec<u8>) -> Result<(), AssemblerError> {
+        if offset > u32::from(u16::MAX) {
+            return Err(AssemblerError::OffsetOutOfRange { operand, offset, span: self.span.clone() });
+        }
+        results.push((offset >> 8) as u8);
+        results.push(offset as u8);
+        Ok(())
     }
 }
 
@@ -188,7 +300,7 @@ mod tests {
     #![allow(unused_imports)]
 
     use super::*;
-    use instruction::Opcode;
+    use assembler::symbols::{Symbol, SymbolType};
 
     #[test]
     fn test_parse_instruction_from_one() {
@@ -204,6 +316,7 @@ mod tests {
                     operand1: None,
                     operand2: None,
                     operand3: None,
+                    span: SourceSpan::default(),
                 }
             ))
         );
@@ -223,6 +336,7 @@ mod tests {
                     operand1: Some(Token::Register { reg_num: 0 }),
                     operand2: Some(Token::IntegerOperand { value: 100 }),
                     operand3: None,
+                    span: SourceSpan::default(),
                 }
             ))
         );
@@ -237,10 +351,168 @@ mod tests {
             operand1: None,
             operand2: None,
             operand3: None,
+            span: SourceSpan::default(),
         };
         let s = SymbolTable::new();
-        let result = instruction.to_bytes(&s);
+        let result = instruction.to_bytes(&s).unwrap();
         assert_eq!(result.len(), 4)
     }
+
+    #[test]
+    fn test_offset_to_bytes() {
+        let result = instruction_combined(CompleteStr("lw $1 $2 #12\n"));
+        let (_, instruction) = result.unwrap();
+        let s = SymbolTable::new();
+        assert_eq!(instruction.to_bytes(&s).unwrap(), vec![23, 1, 2, 12]);
+    }
+
+    #[test]
+    fn test_comparison_destination_to_bytes() {
+        let s = SymbolTable::new();
+        let (_, instruction) = instruction_combined(CompleteStr("eq $1 $2\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&s).unwrap(), vec![9, 1, 2, NO_REGISTER]);
+        let (_, instruction) = instruction_combined(CompleteStr("eq $1 $2 $3\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&s).unwrap(), vec![9, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_literal_errors() {
+        let s = SymbolTable::new();
+        let (_, instruction) = instruction_combined(CompleteStr("load $0 #0xffff\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&s).unwrap(), vec![0, 0, 255, 255]);
+        let (_, instruction) = instruction_combined(CompleteStr("loadhi $0 #-1\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&s).unwrap(), vec![55, 0, 255, 255]);
+
+        let (_, instruction) = instruction_combined(CompleteStr("load $0 #-1\n")).unwrap();
+        match instruction.to_bytes(&s) {
+            Err(AssemblerError::LiteralOutOfRange { value, min, max, .. }) => {
+                assert_eq!(value, -1);
+                assert_eq!((min, max), (0, 0xffff));
+            },
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+        let (_, instruction) = instruction_combined(CompleteStr("jmpfe #-4\n")).unwrap();
+        assert!(instruction.to_bytes(&s).is_err());
+
+        let (_, instruction) = instruction_combined(CompleteStr("load $0 #100000\n")).unwrap();
+        match instruction.to_bytes(&s) {
+            Err(AssemblerError::LiteralOutOfRange { value, bits, .. }) => {
+                assert_eq!(value, 100000);
+                assert_eq!(bits, 16);
+            },
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+
+        let (_, instruction) = instruction_combined(CompleteStr("lw $1 $2 #256\n")).unwrap();
+        assert!(instruction.to_bytes(&s).is_err());
+    }
+
+    #[test]
+    fn test_label_usage_to_bytes() {
+        let instruction = AssemblerInstruction {
+            opcode: Some(Token::Op { code: Opcode::DJMPE }),
+            label: None,
+            directive: None,
+            operand1: Some(Token::LabelUsage { name: "test".to_string() }),
+            operand2: None,
+            operand3: None,
+            span: SourceSpan::default(),
+        };
+        let mut s = SymbolTable::new();
+        s.add_symbol(Symbol::new_with_offset("test".to_string(), SymbolType::Label, 300));
+        let result = instruction.to_bytes(&s);
+        assert_eq!(result.unwrap(), vec![20, 1, 44, 0]);
+
+        let result = instruction.to_bytes(&SymbolTable::new());
+        match result {
+            Err(AssemblerError::UndefinedSymbol { ref name, .. }) => assert_eq!(name, "test"),
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+
+        let mut s = SymbolTable::new();
+        s.add_symbol(Symbol::new_with_offset("test".to_string(), SymbolType::Label, 0x10000));
+        match instruction.to_bytes(&s) {
+            Err(AssemblerError::OffsetOutOfRange { ref operand, offset, .. }) => {
+                assert_eq!(operand, "@test");
+                assert_eq!(offset, 0x10000);
+            },
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_float_operand_to_bytes() {
+        let (_, instruction) = instruction_combined(CompleteStr("loadf64 $0 #1.5\n")).unwrap();
+        let mut s = SymbolTable::new();
+        match instruction.to_bytes(&s) {
+            Err(AssemblerError::MissingFloatConstant { value, .. }) => assert_eq!(value, 1.5),
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+        s.add_float_constant(1.5, 0x102);
+        assert_eq!(instruction.to_bytes(&s).unwrap(), vec![42, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_invalid_instructions_to_bytes() {
+        let s = SymbolTable::new();
+        let instruction = AssemblerInstruction {
+            opcode: None,
+            label: None,
+            directive: Some(Token::Directive { name: "code".to_string() }),
+            operand1: None,
+            operand2: None,
+            operand3: None,
+            span: SourceSpan::default(),
+        };
+        match instruction.to_bytes(&s) {
+            Err(AssemblerError::NonOpcodeInOpcodeField { .. }) => {},
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+
+        let (_, instruction) = instruction_combined(CompleteStr("frobnicate $1\n")).unwrap();
+        match instruction.to_bytes(&s) {
+            Err(AssemblerError::NonOpcodeInOpcodeField { .. }) => {},
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+
+        let instruction = AssemblerInstruction {
+            opcode: Some(Token::Op { code: Opcode::PRTS }),
+            label: None,
+            directive: None,
+            operand1: Some(Token::IrString { name: "hello".to_string() }),
+            operand2: None,
+            operand3: None,
+            span: SourceSpan::default(),
+        };
+        match instruction.extract_operand(instruction.operand1.as_ref().unwrap(), Opcode::DJMP, OperandKind::Address, &mut vec![], &s) {
+            Err(AssemblerError::InvalidOperand { .. }) => {},
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_operand_mismatch() {
+        let s = SymbolTable::new();
+        let cases = vec![
+            ("hlt $1 $2 $3\n", "no operands", "register, register, register"),
+            ("add #5\n", "register, register, register", "integer"),
+            ("load $0\n", "register, imm16", "register"),
+            ("prts 'hello'\n", "label/imm16", "string"),
+            ("eq $1 #2\n", "register, register, [register]", "register, integer"),
+        ];
+        for (source, expected_kinds, found_kinds) in cases {
+            let (_, instruction) = instruction_combined(CompleteStr(source)).unwrap();
+            match instruction.to_bytes(&s) {
+                Err(AssemblerError::OperandMismatch { ref expected, ref found, .. }) => {
+                    assert_eq!(expected, expected_kinds);
+                    assert_eq!(found, found_kinds);
+                },
+                ref r => panic!("unexpected result for {:?}: {:?}", source, r),
+            }
+        }
+
+        let (_, instruction) = instruction_combined(CompleteStr("djmp #8\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&s).unwrap(), vec![31, 0, 8, 0]);
+    }
 }
 
@@ -5,7 +5,7 @@ use nom::multispace;
 use assembler::Token;
 
 named!(pub label_declaration<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             name: alphanumeric >>
             tag!(":") >>
@@ -18,7 +18,7 @@ named!(pub label_declaration<CompleteStr, Token>,
 );
 
 named!(pub label_usage<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!("@") >>
             name: alphanumeric >>
@@ -32,6 +32,7 @@ named!(pub label_usage<CompleteStr, Token>,
 
 mod tests{
     #![allow(unused_imports)]
+    #![allow(clippy::bool_assert_comparison)]
 
     use super::*;
 
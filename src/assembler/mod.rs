@@ -1,4 +1,6 @@
 pub mod assembler_errors;
+#[macro_use]
+pub mod comment_parsers;
 pub mod opcode_parsers;
 pub mod operand_parsers;
 pub mod register_parsers;
@@ -8,25 +10,28 @@ pub mod label_parsers;
 pub mod directive_parsers;
 pub mod symbols;
 
+use byteorder::{BigEndian, WriteBytesExt};
 use nom::types::CompleteStr;
 
-use assembler::assembler_errors::AssemblerError;
+use assembler::assembler_errors::{AssemblerError, SourceSpan};
 use assembler::instruction_parsers::AssemblerInstruction;
-use assembler::program_parsers::program;
+use assembler::program_parsers::program_with_recovery;
 use assembler::program_parsers::Program;
 use assembler::symbols::Symbol;
 use assembler::symbols::SymbolTable;
 use assembler::symbols::SymbolType;
 use instruction::Opcode;
+use pie::PieHeader;
 
-pub const PIE_HEADER_PREFIX: [u8; 4] = [0x45, 0x50, 0x49, 0x45];
-pub const PIE_HEADER_LENGTH: usize = 64;
+pub use pie::PIE_HEADER_LENGTH;
+pub use pie::PIE_HEADER_PREFIX;
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
     Op{code: Opcode},
     Register{reg_num: u8},
-    IntegerOperand{value: i32},
+    IntegerOperand{value: i64},
+    FloatOperand{value: f64},
     LabelDeclaration{name: String},
     LabelUsage{name: String },
     Directive{name: String },
@@ -46,7 +51,7 @@ pub enum AssemblerSection {
     Unknown,
 }
 
-impl<'a> From<&'a str> for AssemblerSection {
+impl From<&str> for AssemblerSection {
     fn from(name: &str) -> AssemblerSection {
         match name {
             "data" => AssemblerSection::Data { starting_instruction: None },
@@ -65,12 +70,26 @@ pub struct Assembler {
     ro_offset: u32,
     sections: Vec<AssemblerSection>,
     current_section: Option<AssemblerSection>,
-    current_instruction: u32,
+    code_offset: u32,
+    entry_label: Option<(String, SourceSpan)>,
+    file_name: String,
     errors: Vec<AssemblerError>,
 }
 
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
+}
+
 impl Assembler {
     pub fn new() -> Assembler {
+        Assembler::with_file_name("<input>")
+    }
+
+    /// Creates an assembler that names `file_name` in the spans of the
+    /// errors it reports.
+    pub fn with_file_name(file_name: &str) -> Assembler {
         Assembler {
             phase: AssemblerPhase::First,
             symbols: SymbolTable::new(),
@@ -79,45 +98,62 @@ impl Assembler {
             ro_offset: 0,
             sections: vec![],
             current_section: None,
-            current_instruction: 0,
+            code_offset: 0,
+            entry_label: None,
+            file_name: file_name.to_string(),
             errors: vec![],
         }
     }
 
     pub fn assemble(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
-        match program(CompleteStr(raw)) {
-            Ok((_remainder, program)) => {
-                let mut assembled_program = self.write_pie_header();
-                self.process_first_phase(&program);
-                if !self.errors.is_empty() {
-                    return Err(self.errors.clone());
-                }
-
-                if self.sections.len() != 2 {
-                    println!("Did not find at least two sections.");
-                    self.errors.push(AssemblerError::InsufficientSections);
-                    return Err(self.errors.clone());
-                }
+        let (mut program, unparsed) = program_with_recovery(CompleteStr(raw));
+        for offset in unparsed {
+            let text = raw[offset..].lines().next().unwrap_or("").trim_end().to_string();
+            let span = SourceSpan::from_offset(&self.file_name, raw, offset);
+            self.errors.push(AssemblerError::UnparsedInput{ text, span });
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
 
-                let mut body = self.process_second_phase(&program);
+        for i in &mut program.instructions {
+            i.span.file = self.file_name.clone();
+        }
+        for c in &mut program.comments {
+            c.span.file = self.file_name.clone();
+        }
+        self.process_first_phase(&program);
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
 
-                assembled_program.append(&mut body);
-                Ok(assembled_program)
-            },
-            Err(e) => {
-                println!("There was an error assembling the code: {:?}", e);
-                Err(vec![AssemblerError::ParseError{ error: e.to_string() }])
-            }
+        if self.sections.len() != 2 {
+            let span = SourceSpan::from_offset(&self.file_name, raw, raw.len());
+            self.errors.push(AssemblerError::InsufficientSections{ span });
+            return Err(self.errors.clone());
         }
+
+        let code = self.process_second_phase(&program);
+        let entry_point = self.entry_point();
+        let mut code = match code {
+            Ok(code) if self.errors.is_empty() => code,
+            _ => return Err(self.errors.clone()),
+        };
+
+        let mut body = self.ro.clone();
+        body.append(&mut code);
+        let mut assembled_program = self.write_pie_header(&body, entry_point);
+        assembled_program.append(&mut body);
+        Ok(assembled_program)
     }
 
     fn process_first_phase(&mut self, p: &Program) {
         for i in &p.instructions {
             if i.is_label() {
                 if self.current_section.is_some() {
-                    self.process_label_declaration(&i);
+                    self.process_label_declaration(i);
                 } else {
-                    self.errors.push(AssemblerError::NoSegmentDeclarationFound{instruction: self.current_instruction});
+                    self.errors.push(AssemblerError::NoSegmentDeclarationFound{ span: i.span.clone() });
                 }
             }
 
@@ -125,9 +161,14 @@ impl Assembler {
                 self.process_directive(i);
             }
 
-            self.current_instruction += 1;
+            if i.is_opcode() {
+                self.process_float_constants(i);
+                self.code_offset += 4;
+            }
         }
 
+        let code_start = PIE_HEADER_LENGTH as u32 + self.ro.len() as u32;
+        self.symbols.rebase(SymbolType::Label, code_start);
         self.phase = AssemblerPhase::Second;
     }
 
@@ -135,44 +176,48 @@ impl Assembler {
         let name = match i.get_label_name() {
             Some(name) => { name },
             None => {
-                self.errors.push(AssemblerError::StringConstantDeclaredWithoutLabel{instruction: self.current_instruction});
+                self.errors.push(AssemblerError::StringConstantDeclaredWithoutLabel{ span: i.span.clone() });
                 return;
             }
         };
 
         if self.symbols.has_symbol(&name) {
-            self.errors.push(AssemblerError::SymbolAlreadyDeclared);
+            self.errors.push(AssemblerError::SymbolAlreadyDeclared{ name, span: i.span.clone() });
             return;
         }
 
-        let symbol = Symbol::new(name, SymbolType::Label);
+        let symbol = match self.current_section {
+            Some(AssemblerSection::Code { .. }) if i.is_opcode() => Symbol::new_with_offset(name, SymbolType::Label, self.code_offset),
+            _ => Symbol::new(name, SymbolType::IrString),
+        };
         self.symbols.add_symbol(symbol);
     }
 
-    fn process_second_phase(&mut self, p: &Program) -> Vec<u8> {
-        self.current_instruction = 0;
-
+    /// Emits the code section. Every instruction is encoded even after one
+    /// fails, so that all of the errors end up in `self.errors`.
+    fn process_second_phase(&mut self, p: &Program) -> Result<Vec<u8>, AssemblerError> {
         let mut program = vec![];
         for i in &p.instructions {
             if i.is_opcode() {
-                let mut bytes = i.to_bytes(&self.symbols);
-                program.append(&mut bytes);
+                match i.to_bytes(&self.symbols) {
+                    Ok(mut bytes) => program.append(&mut bytes),
+                    Err(e) => self.errors.push(e),
+                }
             }
             if i.is_directive() {
                 self.process_directive(i);
             }
-            self.current_instruction += 1;
         }
-        program
+        match self.errors.first() {
+            Some(e) => Err(e.clone()),
+            None => Ok(program),
+        }
     }
 
     fn process_directive(&mut self, i: &AssemblerInstruction) {
         let directive_name = match i.get_directive_name() {
             Some(name) => name,
-            None => {
-                println!("Directive has an invalid name: {:?}", i);
-                return;
-            },
+            None => return,
         };
 
         if i.has_operands() {
@@ -180,20 +225,22 @@ impl Assembler {
                 "asciiz" => {
                     self.handle_asciiz(i);
                 }
+                "entry" => {
+                    self.handle_entry(i);
+                }
                 _ => {
-                    self.errors.push(AssemblerError::UnknownDirectiveFound{directive: directive_name.clone() });
-                    return;
+                    self.errors.push(AssemblerError::UnknownDirectiveFound{ directive: directive_name.clone(), span: i.span.clone() });
                 }
             }
         } else {
-            self.process_section_header(&directive_name);
+            self.process_section_header(&directive_name, i);
         }
     }
 
-    fn process_section_header(&mut self, header_name: &str) {
+    fn process_section_header(&mut self, header_name: &str, i: &AssemblerInstruction) {
         let new_section: AssemblerSection = header_name.into();
         if new_section == AssemblerSection::Unknown {
-            println!("Found an section header that is unknown: {:#?}", header_name);
+            self.errors.push(AssemblerError::UnknownDirectiveFound{ directive: header_name.to_string(), span: i.span.clone() });
             return;
         }
         self.sections.push(new_section.clone());
@@ -212,7 +259,7 @@ impl Assembler {
                         self.symbols.set_symbol_offset(&name, self.ro_offset);
                     }
                     None => {
-                        println!("Found a string constant with no associated label!");
+                        self.errors.push(AssemblerError::StringConstantDeclaredWithoutLabel{ span: i.span.clone() });
                         return;
                     }
                 };
@@ -224,28 +271,67 @@ impl Assembler {
                 self.ro_offset += 1;
             }
             None => {
-                println!("String constant following an .asciiz was empty");
+                self.errors.push(AssemblerError::InvalidOperand{ span: i.span.clone() });
             }
         }
     }
 
-    fn write_pie_header(&self) -> Vec<u8> {
-        let mut header = vec![];
-        for byte in PIE_HEADER_PREFIX.iter() {
-            header.push(byte.clone());
+    fn process_float_constants(&mut self, i: &AssemblerInstruction) {
+        for value in i.get_float_constants() {
+            if self.symbols.float_constant_offset(value).is_some() {
+                continue;
+            }
+            self.symbols.add_float_constant(value, self.ro_offset);
+            self.ro.write_f64::<BigEndian>(value).unwrap();
+            self.ro_offset += 8;
         }
-        while header.len() < PIE_HEADER_LENGTH {
-            header.push(0);
+    }
+
+    fn handle_entry(&mut self, i: &AssemblerInstruction) {
+        if self.phase != AssemblerPhase::First {
+            return;
         }
-        header
+
+        match i.get_label_usages().pop() {
+            Some(name) => {
+                self.entry_label = Some((name, i.span.clone()));
+            }
+            None => {
+                self.errors.push(AssemblerError::InvalidOperand{ span: i.span.clone() });
+            }
+        }
+    }
+
+    fn entry_point(&mut self) -> u32 {
+        let code_start = PIE_HEADER_LENGTH as u32 + self.ro.len() as u32;
+        let (name, span) = match self.entry_label {
+            Some(ref entry) => entry.clone(),
+            None => return code_start,
+        };
+        if let Some(offset) = self.symbols.label_offset(&name) {
+            return offset;
+        }
+        if self.symbols.has_symbol(&name) {
+            self.errors.push(AssemblerError::EntryNotCodeLabel{ name, span });
+        } else {
+            self.errors.push(AssemblerError::UndefinedSymbol{ name, span });
+        }
+        code_start
+    }
+
+    fn write_pie_header(&self, body: &[u8], entry_point: u32) -> Vec<u8> {
+        PieHeader::new(body, self.ro.len() as u32, entry_point).to_bytes()
     }
 }
 
 mod tests {
     #![allow(unused_imports)]
+    #![allow(clippy::bool_assert_comparison)]
 
     use super::Assembler;
+    use super::AssemblerError;
     use super::PIE_HEADER_LENGTH;
+    use pie::{PieHeader, PieSection};
     use vm::VM;
 
     #[test]
@@ -259,7 +345,7 @@ mod tests {
             load $2 #0
             test: inc $0
             neq $0 $2
-            jmpe @test
+            djmpe @test
             hlt
         ";
         let result = asm.assemble(test_string);
@@ -271,4 +357,178 @@ mod tests {
         vm.add_bytes(program);
         assert_eq!(vm.program.len(), 28 + PIE_HEADER_LENGTH);
     }
+
+    #[test]
+    fn test_label_offsets() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+            .data
+            .code
+            load $0 #100
+            test: inc $0
+            djmpe @test
+            hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let offset = PIE_HEADER_LENGTH as u32 + 4;
+        assert_eq!(asm.symbols.symbol_value("test"), Some(offset));
+        let djmpe = &program[PIE_HEADER_LENGTH + 8..PIE_HEADER_LENGTH + 12];
+        assert_eq!(djmpe, &[20, (offset >> 8) as u8, offset as u8, 0]);
+    }
+
+    #[test]
+    fn test_ro_section() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+            .data
+            hello: .asciiz 'Hello'
+            .code
+            loop: prts @hello
+            djmpe @loop
+            hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(program.len(), PIE_HEADER_LENGTH + 6 + 12);
+        let header = PieHeader::parse(&program).unwrap();
+        assert_eq!(header.ro, PieSection::new(64, 6));
+        assert_eq!(header.code, PieSection::new(70, 12));
+        assert_eq!(&program[PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 6], b"Hello\0");
+        assert_eq!(asm.symbols.symbol_value("hello"), Some(0));
+        assert_eq!(asm.symbols.symbol_value("loop"), Some(PIE_HEADER_LENGTH as u32 + 6));
+    }
+
+    #[test]
+    fn test_asciiz_keeps_string_contents() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\na: .asciiz '; hi'\nb: .asciiz '  lead'\n.code\nhlt\n").unwrap();
+        assert_eq!(&program[PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 12], b"; hi\0  lead\0");
+    }
+
+    #[test]
+    fn test_float_constants_in_ro_section() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+            .data
+            hello: .asciiz 'Hi'
+            .code
+            loadf64 $0 #1.5
+            loadf64 $1 #-2.0
+            loadf64 $2 #1.5
+            hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let header = PieHeader::parse(&program).unwrap();
+        assert_eq!(header.ro, PieSection::new(64, 3 + 16));
+        assert_eq!(asm.symbols.float_constant_offset(1.5), Some(3));
+        assert_eq!(asm.symbols.float_constant_offset(-2.0), Some(11));
+        let code = header.code.start();
+        assert_eq!(&program[code..code + 4], &[42, 0, 0, 3]);
+        assert_eq!(&program[code + 8..code + 12], &[42, 2, 0, 3]);
+    }
+
+    #[test]
+    fn test_entry_directive() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+            .data
+            .entry main
+            .code
+            helper: inc $0
+            hlt
+            main: load $0 #1
+            hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let header = PieHeader::parse(&program).unwrap();
+        assert_eq!(header.entry_point, PIE_HEADER_LENGTH as u32 + 8);
+
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nhlt\n").unwrap();
+        let header = PieHeader::parse(&program).unwrap();
+        assert_eq!(header.entry_point, PIE_HEADER_LENGTH as u32);
+
+        let mut asm = Assembler::new();
+        let result = asm.assemble(".data\n.entry @missing\n.code\nhlt\n");
+        assert_eq!(result.is_err(), true);
+
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\ns: .asciiz 'hi'\n.entry s\n.code\nhlt\n").unwrap_err();
+        match errors[0] {
+            AssemblerError::EntryNotCodeLabel { ref name, .. } => assert_eq!(name, "s"),
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_literal_out_of_range() {
+        let mut asm = Assembler::new();
+        let result = asm.assemble(".data\n.code\nload $0 #70000\nhlt\n");
+        match result.unwrap_err()[0] {
+            AssemblerError::LiteralOutOfRange { value, bits, .. } => {
+                assert_eq!(value, 70000);
+                assert_eq!(bits, 16);
+            },
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_unparsed_input() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\n.code\nload $0 #100 %%%\nhlt\n!!! oops\nhlt\n";
+        let errors = asm.assemble(test_string).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        match errors[0] {
+            AssemblerError::UnparsedInput { ref text, ref span } => {
+                assert_eq!(text, "%%%");
+                assert_eq!((span.line, span.column), (3, 14));
+            },
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+        match errors[1] {
+            AssemblerError::UnparsedInput { ref text, ref span } => {
+                assert_eq!(text, "!!! oops");
+                assert_eq!((span.line, span.column), (5, 1));
+            },
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_undefined_symbol() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+            .data
+            .code
+            djmpe @nowhere
+            hlt
+        ";
+        let result = asm.assemble(test_string);
+        assert_eq!(result.is_err(), true);
+        match result.unwrap_err()[0] {
+            AssemblerError::UndefinedSymbol { ref name, ref span } => {
+                assert_eq!(name, "nowhere");
+                assert_eq!((span.line, span.column), (4, 13));
+            },
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_invalid_directives_are_errors() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.bogus\n.asciiz 'hi'\n.code\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        match errors[0] {
+            AssemblerError::UnknownDirectiveFound { ref directive, ref span } => {
+                assert_eq!(directive, "bogus");
+                assert_eq!(span.line, 2);
+            },
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+        match errors[1] {
+            AssemblerError::StringConstantDeclaredWithoutLabel { ref span } => assert_eq!(span.line, 3),
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+    }
 }
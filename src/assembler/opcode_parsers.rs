@@ -1,11 +1,11 @@
 use assembler::Token;
 use instruction::Opcode;
 use nom::types::CompleteStr;
-use nom::alpha1;
+use nom::alphanumeric1;
 
 named!(pub opcode<CompleteStr, Token>,
    do_parse!(
-       opcode: alpha1 >>
+       opcode: alphanumeric1 >>
        (
            Token::Op{code: Opcode::from(opcode)}
        )
@@ -14,6 +14,7 @@ named!(pub opcode<CompleteStr, Token>,
 
 mod tests {
     #![allow(unused_imports)]
+    #![allow(clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -28,5 +29,10 @@ mod tests {
         let result = opcode(CompleteStr("aold"));
         let (_, token) = result.unwrap();
         assert_eq!(token, Token::Op { code: Opcode::IGL });
+
+        let result = opcode(CompleteStr("loadf64 $0"));
+        let (rest, token) = result.unwrap();
+        assert_eq!(token, Token::Op { code: Opcode::LOADF64 });
+        assert_eq!(rest, CompleteStr(" $0"));
     }
 }
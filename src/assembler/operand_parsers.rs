@@ -1,12 +1,14 @@
 use nom::types::CompleteStr;
-use nom::digit;
+use nom::{digit, hex_digit};
 
 use assembler::Token;
+use assembler::comment_parsers::trivia;
 use assembler::label_parsers::label_usage;
 use assembler::register_parsers::register;
 
 named!(pub operand<CompleteStr, Token>,
     alt!(
+        float_operand |
         integer_operand |
         label_usage |
         register |
@@ -15,32 +17,65 @@ named!(pub operand<CompleteStr, Token>,
 );
 
 named!(integer_operand<CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!("#") >>
-            reg_num: digit >>
+            negative: opt!(tag!("-")) >>
+            magnitude: integer_literal >>
             (
-                Token::IntegerOperand{value: reg_num.parse::<i32>().unwrap()}
+                Token::IntegerOperand{value: if negative.is_some() { -magnitude } else { magnitude }}
             )
         )
     )
 );
 
-named!(irstring<CompleteStr, Token>,
-    ws!(
+named!(integer_literal<CompleteStr, i64>,
+    alt!(
+        preceded!(tag!("0x"), map_res!(hex_digit, |s: CompleteStr| i64::from_str_radix(&s, 16))) |
+        preceded!(tag!("0b"), map_res!(is_a!("01"), |s: CompleteStr| i64::from_str_radix(&s, 2))) |
+        map_res!(digit, |s: CompleteStr| s.parse::<i64>())
+    )
+);
+
+named!(float_operand<CompleteStr, Token>,
+    ws_comments!(
         do_parse!(
-            tag!("'") >>
-            content: take_until!("'") >>
-            tag!("'") >>
+            tag!("#") >>
+            value: recognize!(
+                do_parse!(
+                    opt!(tag!("-")) >>
+                    alt!(
+                        tag!("inf") |
+                        tag!("nan") |
+                        recognize!(do_parse!(digit >> tag!(".") >> digit >> ()))
+                    ) >>
+                    ()
+                )
+            ) >>
             (
-                Token::IrString{ name: content.to_string() }
+                Token::FloatOperand{value: value.parse::<f64>().unwrap()}
             )
         )
     )
 );
 
+// Trivia is only skipped before the opening quote; everything up to the
+// closing quote, including leading spaces and `;`, is part of the string.
+named!(irstring<CompleteStr, Token>,
+    do_parse!(
+        trivia >>
+        tag!("'") >>
+        content: take_until!("'") >>
+        tag!("'") >>
+        (
+            Token::IrString{ name: content.to_string() }
+        )
+    )
+);
+
 mod tests {
     #![allow(unused_imports)]
+    #![allow(clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -55,6 +90,35 @@ mod tests {
         assert_eq!(result.is_ok(), false)
     }
 
+    #[test]
+    fn test_parse_integer_literal_forms() {
+        let cases = [("#-1", -1), ("#0xFF", 255), ("#0b1010", 10), ("#-0x10", -16), ("#100000", 100000)];
+        for (source, expected) in cases.iter() {
+            let result = integer_operand(CompleteStr(source));
+            assert_eq!(result, Ok((CompleteStr(""), Token::IntegerOperand{value: *expected})));
+        }
+        let result = integer_operand(CompleteStr("#99999999999999999999"));
+        assert_eq!(result.is_ok(), false);
+    }
+
+    #[test]
+    fn test_parse_float_operand() {
+        let result = float_operand(CompleteStr("#2.25"));
+        assert_eq!(result, Ok((CompleteStr(""), Token::FloatOperand{value: 2.25})));
+        let result = float_operand(CompleteStr("#-0.5"));
+        assert_eq!(result, Ok((CompleteStr(""), Token::FloatOperand{value: -0.5})));
+        let result = float_operand(CompleteStr("#-inf"));
+        assert_eq!(result, Ok((CompleteStr(""), Token::FloatOperand{value: f64::NEG_INFINITY})));
+        match float_operand(CompleteStr("#nan")) {
+            Ok((_, Token::FloatOperand{value})) => assert!(value.is_nan()),
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+        let result = float_operand(CompleteStr("#3"));
+        assert_eq!(result.is_ok(), false);
+        let result = operand(CompleteStr("#10"));
+        assert_eq!(result, Ok((CompleteStr(""), Token::IntegerOperand{value: 10})));
+    }
+
     #[test]
     fn test_parse_irstring_operand() {
         let result = irstring(CompleteStr("'This is a test'"));
@@ -64,4 +128,17 @@ mod tests {
             result
         );
     }
+
+    #[test]
+    fn test_parse_irstring_is_literal() {
+        for text in &["; hi", "# x", "/* */", "  lead"] {
+            let source = format!("'{}'", text);
+            assert_eq!(
+                irstring(CompleteStr(&source)),
+                Ok((CompleteStr(""), Token::IrString{ name: text.to_string() }))
+            );
+        }
+        let result = irstring(CompleteStr(" /* note */ '; hi'"));
+        assert_eq!(result, Ok((CompleteStr(""), Token::IrString{ name: "; hi".to_string() })));
+    }
 }
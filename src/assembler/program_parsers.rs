@@ -1,5 +1,10 @@
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
+use nom::{Context, Err, ErrorKind, IResult};
 use nom::types::CompleteStr;
 
+use assembler::assembler_errors::{AssemblerError, SourceSpan};
+use assembler::comment_parsers::{find_comments, trivia};
 use assembler::directive_parsers::directive;
 use assembler::instruction_parsers::{AssemblerInstruction, instruction};
 use assembler::SymbolTable;
@@ -7,29 +12,140 @@ use assembler::SymbolTable;
 #[derive(Debug, PartialEq)]
 pub struct Program {
     pub instructions: Vec<AssemblerInstruction>,
+    /// Every comment in the source, in order, so that tools which print a
+    /// program back out can keep them.
+    pub comments: Vec<Comment>,
+}
+
+/// A `; line` or `/* block */` comment, including its delimiters.
+#[derive(Debug, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: SourceSpan,
 }
 
 impl Program {
-    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, AssemblerError> {
         let mut program = vec![];
         for instruction in &self.instructions {
-            program.append(&mut instruction.to_bytes(symbols));
+            program.append(&mut instruction.to_bytes(symbols)?);
         }
-        program
+        Ok(program)
     }
 }
 
-named!(pub program<CompleteStr, Program>,
-    do_parse!(
-        instructions: many1!(alt!(instruction | directive)) >>
-        (
-            Program {
-                instructions: instructions,
-            }
-        )
-    )
+named!(program_instruction<CompleteStr, AssemblerInstruction>,
+    alt!(instruction | directive)
 );
 
+/// Parses one or more instructions and directives, recording the line and
+/// column each one starts at in its `span`. Parsing stops at the first input
+/// that is neither, which is returned as the remainder.
+pub fn program(input: CompleteStr) -> IResult<CompleteStr, Program> {
+    let (rest, program, _) = parse_instructions(input, false);
+    if program.instructions.is_empty() {
+        return Err(Err::Error(Context::Code(rest, ErrorKind::Many1)));
+    }
+    Ok((rest, program))
+}
+
+/// Parses like `program`, but when a line cannot be parsed it skips to the
+/// next line and carries on. Returns the byte offset of every piece of input
+/// that was skipped, so that all syntax errors can be reported at once.
+pub fn program_with_recovery(input: CompleteStr) -> (Program, Vec<usize>) {
+    let (_, program, unparsed) = parse_instructions(input, true);
+    (program, unparsed)
+}
+
+fn parse_instructions(input: CompleteStr, recover: bool) -> (CompleteStr, Program, Vec<usize>) {
+    let mut instructions = vec![];
+    let mut unparsed = vec![];
+    let mut comment_offsets = vec![];
+    let mut rest = input;
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut scanned = 0;
+    loop {
+        let remaining = skip_whitespace(rest);
+        let start = input.len() - remaining.len();
+        record_comments(&input[input.len() - rest.len()..start], input.len() - rest.len(), &mut comment_offsets);
+        if remaining.is_empty() {
+            rest = remaining;
+            break;
+        }
+        match program_instruction(remaining) {
+            Ok((remaining, mut instruction)) => {
+                let end = input.len() - remaining.len();
+                record_comments(&input[start..end], start, &mut comment_offsets);
+                for (i, c) in input[scanned..start].char_indices() {
+                    if c == '\n' {
+                        line += 1;
+                        line_start = scanned + i + 1;
+                    }
+                }
+                scanned = start;
+                instruction.span = SourceSpan {
+                    file: String::new(),
+                    line,
+                    column: input[line_start..start].chars().count() + 1,
+                };
+                instructions.push(instruction);
+                rest = remaining;
+            }
+            Err(_) if recover => {
+                unparsed.push(start);
+                let line_end = remaining.find('\n').unwrap_or_else(|| remaining.len());
+                rest = CompleteStr(&remaining[line_end..]);
+            }
+            Err(_) => {
+                rest = remaining;
+                break;
+            }
+        }
+    }
+    let comments = comment_spans(&input, comment_offsets);
+    (rest, Program { instructions, comments }, unparsed)
+}
+
+fn skip_whitespace(input: CompleteStr) -> CompleteStr {
+    match trivia(input) {
+        Ok((rest, _)) => rest,
+        Err(_) => input,
+    }
+}
+
+fn record_comments<'a>(source: &'a str, base: usize, offsets: &mut Vec<(usize, &'a str)>) {
+    for (offset, text) in find_comments(source) {
+        offsets.push((base + offset, text));
+    }
+}
+
+// Offsets arrive in order, so lines are counted in a single pass.
+fn comment_spans(input: &str, offsets: Vec<(usize, &str)>) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut scanned = 0;
+    for (offset, text) in offsets {
+        for (i, c) in input[scanned..offset].char_indices() {
+            if c == '\n' {
+                line += 1;
+                line_start = scanned + i + 1;
+            }
+        }
+        scanned = offset;
+        comments.push(Comment {
+            text: text.to_string(),
+            span: SourceSpan {
+                file: String::new(),
+                line,
+                column: input[line_start..offset].chars().count() + 1,
+            },
+        });
+    }
+    comments
+}
+
 #[test]
 fn test_parse_program() {
     let result = program(CompleteStr("load $0 #100\n"));
@@ -45,11 +161,55 @@ fn test_program_to_bytes() {
     assert_eq!(result.is_ok(), true);
     let (_, program) = result.unwrap();
     let symbols = SymbolTable::new();
-    let bytecode = program.to_bytes(&symbols);
+    let bytecode = program.to_bytes(&symbols).unwrap();
     assert_eq!(bytecode.len(), 4);
     println!("{:?}", bytecode);
 }
 
+#[test]
+fn test_program_spans() {
+    let result = program(CompleteStr(".data\n.code\n  load $0 #100\n\thlt"));
+    let (_, p) = result.unwrap();
+    let positions: Vec<(usize, usize)> = p.instructions.iter().map(|i| (i.span.line, i.span.column)).collect();
+    assert_eq!(positions, vec![(1, 1), (2, 1), (3, 3), (4, 2)]);
+}
+
+#[test]
+fn test_program_stops_at_unparsed_input() {
+    let result = program(CompleteStr("load $0 #100\n!!!\nhlt\n"));
+    let (leftover, p) = result.unwrap();
+    assert_eq!(leftover, CompleteStr("!!!\nhlt\n"));
+    assert_eq!(1, p.instructions.len());
+
+    let result = program(CompleteStr("   \n"));
+    assert_eq!(result.is_ok(), false);
+}
+
+#[test]
+fn test_program_with_recovery() {
+    let source = "load $0 #100\n!!!\nload $1 #1 ???\nhlt\n";
+    let (p, unparsed) = program_with_recovery(CompleteStr(source));
+    assert_eq!(3, p.instructions.len());
+    assert_eq!(p.instructions[2].span.line, 4);
+    assert_eq!(unparsed, vec![13, 28]);
+}
+
+#[test]
+fn test_program_comments() {
+    let source = "; header\n.data\n.code ; start\nload $0 /* count */ #100\n\n/* multi\nline */\nhlt ; done\n";
+    let (rest, p) = program(CompleteStr(source)).unwrap();
+    assert_eq!(rest, CompleteStr(""));
+    assert_eq!(p.instructions.len(), 4);
+    let comments: Vec<(&str, usize, usize)> = p.comments.iter().map(|c| (&c.text[..], c.span.line, c.span.column)).collect();
+    assert_eq!(comments, vec![
+        ("; header", 1, 1),
+        ("; start", 3, 7),
+        ("/* count */", 4, 9),
+        ("/* multi\nline */", 6, 1),
+        ("; done", 8, 5),
+    ]);
+}
+
 #[test]
 fn test_complete_program() {
     let test_program = CompleteStr(r"
@@ -3,14 +3,24 @@ use nom::digit;
 
 use assembler::Token;
 
+/// The VM has 32 integer and 32 float registers.
+pub const REGISTER_COUNT: u8 = 32;
+
+fn register_number(input: CompleteStr) -> Result<u8, String> {
+    match input.parse::<u8>() {
+        Ok(reg_num) if reg_num < REGISTER_COUNT => Ok(reg_num),
+        _ => Err(format!("${} is not a register", input)),
+    }
+}
+
 named!(pub register <CompleteStr, Token>,
-    ws!(
+    ws_comments!(
         do_parse!(
             tag!("$") >>
-            reg_num: digit >>
+            reg_num: map_res!(digit, register_number) >>
             (
                 Token::Register{
-                    reg_num: reg_num.parse::<u8>().unwrap()
+                    reg_num
                 }
             )
         )
@@ -19,6 +29,7 @@ named!(pub register <CompleteStr, Token>,
 
 mod tests {
     #![allow(unused_imports)]
+    #![allow(clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -30,6 +41,12 @@ mod tests {
         assert_eq!(result.is_ok(), false);
         let result = register(CompleteStr("$a"));
         assert_eq!(result.is_ok(), false);
+        let result = register(CompleteStr("$31"));
+        assert_eq!(result.is_ok(), true);
+        let result = register(CompleteStr("$32"));
+        assert_eq!(result.is_ok(), false);
+        let result = register(CompleteStr("$300"));
+        assert_eq!(result.is_ok(), false);
 
     }
 }
@@ -23,20 +23,23 @@ impl Symbol {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum SymbolType {
     Label,
+    IrString,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SymbolTable {
-    symbols: Vec<Symbol>
+    symbols: Vec<Symbol>,
+    float_constants: Vec<(u64, u32)>,
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         SymbolTable{
             symbols: vec![],
+            float_constants: vec![],
         }
     }
 
@@ -71,10 +74,48 @@ impl SymbolTable {
         }
         None
     }
+
+    /// Records that the f64 `value` is stored at `offset` in the read-only
+    /// section.
+    pub fn add_float_constant(&mut self, value: f64, offset: u32) {
+        self.float_constants.push((value.to_bits(), offset));
+    }
+
+    pub fn float_constant_offset(&self, value: f64) -> Option<u32> {
+        for (bits, offset) in &self.float_constants {
+            if *bits == value.to_bits() {
+                return Some(*offset);
+            }
+        }
+        None
+    }
+
+    /// Like `symbol_value`, but only for labels in the code section.
+    pub fn label_offset(&self, s: &str) -> Option<u32> {
+        self.symbols.iter()
+            .find(|symbol| symbol.name == s && symbol.symbol_type == SymbolType::Label)
+            .and_then(|symbol| symbol.offset)
+    }
+
+    /// The name of the label at `offset`, if there is one.
+    pub fn label_at(&self, offset: u32) -> Option<&str> {
+        self.symbols.iter()
+            .find(|symbol| symbol.offset == Some(offset) && symbol.symbol_type == SymbolType::Label)
+            .map(|symbol| &symbol.name[..])
+    }
+
+    pub fn rebase(&mut self, symbol_type: SymbolType, base: u32) {
+        for symbol in &mut self.symbols {
+            if symbol.symbol_type == symbol_type {
+                symbol.offset = symbol.offset.map(|offset| offset + base);
+            }
+        }
+    }
 }
 
 mod tests {
     #![allow(unused_imports)]
+    #![allow(clippy::bool_assert_comparison)]
 
     use super::*;
 
@@ -91,5 +132,34 @@ mod tests {
         let v = sym.symbol_value("does_not_exist");
         assert_eq!(v.is_some(), false);
     }
+
+    #[test]
+    fn test_rebase() {
+        let mut sym = SymbolTable::new();
+        sym.add_symbol(Symbol::new_with_offset("loop".to_string(), SymbolType::Label, 8));
+        sym.add_symbol(Symbol::new_with_offset("hello".to_string(), SymbolType::IrString, 0));
+        sym.rebase(SymbolType::Label, 64);
+        assert_eq!(sym.symbol_value("loop"), Some(72));
+        assert_eq!(sym.symbol_value("hello"), Some(0));
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut sym = SymbolTable::new();
+        sym.add_symbol(Symbol::new_with_offset("loop".to_string(), SymbolType::Label, 72));
+        sym.add_symbol(Symbol::new_with_offset("hello".to_string(), SymbolType::IrString, 0));
+        assert_eq!(sym.label_offset("loop"), Some(72));
+        assert_eq!(sym.label_offset("hello"), None);
+        assert_eq!(sym.label_at(72), Some("loop"));
+        assert_eq!(sym.label_at(0), None);
+    }
+
+    #[test]
+    fn test_float_constants() {
+        let mut sym = SymbolTable::new();
+        sym.add_float_constant(1.5, 6);
+        assert_eq!(sym.float_constant_offset(1.5), Some(6));
+        assert_eq!(sym.float_constant_offset(-1.5), None);
+    }
 }
 
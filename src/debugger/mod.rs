@@ -0,0 +1,361 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::Write;
+
+use assembler::symbols::SymbolTable;
+use disassembler::hex;
+use instruction::Opcode;
+use vm::VM;
+use vm::vm_errors::{ExitReason, VmError};
+
+const HELP: &str = "\
+break <label|offset>   stop before the instruction at a label or image offset
+step                   run one instruction
+next                   run one instruction, running a whole `call` at once
+continue               run until a breakpoint, a watch fires or the program ends
+watch $<register>      stop whenever the register changes
+budget <amount>        add to the instruction budget, starting one if there is none
+print $<register>      show a register
+print heap[<a>..<b>]   show heap bytes a up to b
+quit                   leave the debugger";
+
+/// Why execution handed control back to the user.
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Stepped,
+    Breakpoint,
+    Watch { register: usize, old: i32, new: i32 },
+    Finished(ExitReason),
+    Trap(VmError),
+}
+
+/// Drives a `VM` one instruction at a time on behalf of a user.
+pub struct Debugger {
+    vm: VM,
+    symbols: SymbolTable,
+    breakpoints: BTreeSet<usize>,
+    watches: BTreeSet<usize>,
+    finished: bool,
+    /// Whether anything has run yet. Until then a breakpoint on the entry
+    /// point still counts; afterwards the instruction at `pc` is the one
+    /// being resumed from, so its breakpoint has already been reported.
+    started: bool,
+}
+
+impl Debugger {
+    /// Loads a PIE image. `symbols` is used to resolve label names and can
+    /// be empty when only the image is available.
+    pub fn new(image: Vec<u8>, symbols: SymbolTable) -> Result<Debugger, VmError> {
+        let mut vm = VM::new();
+        vm.add_bytes(image);
+        vm.load()?;
+        Ok(Debugger {
+            vm,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watches: BTreeSet::new(),
+            finished: false,
+            started: false,
+        })
+    }
+
+    pub fn run(&mut self) {
+        println!("{}", self.location());
+        loop {
+            print!("(idb) ");
+            io::stdout().flush().expect("Unable to flush stdout");
+            let mut buffer = String::new();
+            match io::stdin().read_line(&mut buffer) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {},
+            }
+            let command = buffer.trim();
+            if command == "quit" || command == "q" {
+                return;
+            }
+            println!("{}", self.execute_command(command));
+        }
+    }
+
+    /// Runs one debugger command and returns what to show the user.
+    pub fn execute_command(&mut self, command: &str) -> String {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let argument = words.next();
+        match (name, argument) {
+            ("break", Some(target)) | ("b", Some(target)) => self.set_breakpoint(target),
+            ("step", None) | ("s", None) => self.resume(|d| d.step()),
+            ("next", None) | ("n", None) => self.resume(|d| d.next()),
+            ("continue", None) | ("c", None) => self.resume(|d| d.continue_execution()),
+            ("watch", Some(register)) => match parse_register(register) {
+                Some(register) => {
+                    self.watches.insert(register);
+                    format!("watching ${}", register)
+                },
+                None => format!("`{}` is not a register", register),
+            },
+            ("budget", Some(amount)) => match parse_number(amount) {
+                Some(amount) => {
+                    self.vm.add_budget(amount as u64);
+                    format!("the instruction budget is now {}", self.vm.budget.unwrap_or(0))
+                },
+                None => format!("`{}` is not a number", amount),
+            },
+            ("print", Some(target)) | ("p", Some(target)) => self.print(target),
+            ("help", None) | ("", None) => HELP.to_string(),
+            _ => format!("unknown command `{}`; try `help`", command),
+        }
+    }
+
+    fn set_breakpoint(&mut self, target: &str) -> String {
+        let offset = match self.symbols.label_offset(target) {
+            Some(offset) => offset as usize,
+            None => match parse_number(target) {
+                Some(offset) => offset,
+                None => return format!("no label named `{}`", target),
+            },
+        };
+        self.breakpoints.insert(offset);
+        format!("breakpoint at {}", self.describe_offset(offset))
+    }
+
+    fn resume<F: FnOnce(&mut Debugger) -> Stop>(&mut self, run: F) -> String {
+        if self.finished {
+            return "the program has finished".to_string();
+        }
+        let stop = run(self);
+        self.started = true;
+        match stop {
+            Stop::Stepped => self.location(),
+            Stop::Breakpoint => format!("breakpoint hit\n{}", self.location()),
+            Stop::Watch { register, old, new } => format!("${} changed from {} to {}\n{}", register, old, new, self.location()),
+            Stop::Finished(ExitReason::Exit(code)) => format!("the program exited with status {}", code),
+            Stop::Finished(ExitReason::BudgetExhausted) => format!("the instruction budget ran out; use `budget` to add more\n{}", self.location()),
+            Stop::Finished(ExitReason::Halted) => "the program halted".to_string(),
+            Stop::Finished(ExitReason::EndOfProgram) => "the program ran past its last instruction".to_string(),
+            Stop::Trap(e) => format!("the program trapped: {}", e),
+        }
+    }
+
+    fn step(&mut self) -> Stop {
+        let watched: Vec<(usize, i32)> = self.watches.iter().map(|r| (*r, self.vm.registers[*r])).collect();
+        match self.vm.run_once() {
+            // The program can carry on once more budget is added.
+            Ok(Some(ExitReason::BudgetExhausted)) => return Stop::Finished(ExitReason::BudgetExhausted),
+            Ok(Some(reason)) => {
+                self.finished = true;
+                return Stop::Finished(reason);
+            },
+            Err(e) => {
+                self.finished = true;
+                return Stop::Trap(e);
+            },
+            Ok(None) => {},
+        }
+        for (register, old) in watched {
+            let new = self.vm.registers[register];
+            if new != old {
+                return Stop::Watch { register, old, new };
+            }
+        }
+        Stop::Stepped
+    }
+
+    // Steps over a `call` by running until the stack is back to where it
+    // was before it.
+    fn next(&mut self) -> Stop {
+        if self.at_entry_breakpoint() {
+            return Stop::Breakpoint;
+        }
+        if self.current_opcode() != Some(Opcode::CALL) {
+            return self.step();
+        }
+        let depth = self.vm.stack_depth();
+        loop {
+            match self.step() {
+                Stop::Stepped => {},
+                stop => return stop,
+            }
+            if self.vm.stack_depth() <= depth {
+                return Stop::Stepped;
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    fn continue_execution(&mut self) -> Stop {
+        if self.at_entry_breakpoint() {
+            return Stop::Breakpoint;
+        }
+        loop {
+            match self.step() {
+                Stop::Stepped => {},
+                stop => return stop,
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    // Breakpoints are otherwise checked after each step, which never looks
+    // at the instruction execution starts from.
+    fn at_entry_breakpoint(&self) -> bool {
+        !self.started && self.breakpoints.contains(&self.vm.pc())
+    }
+
+    fn print(&self, target: &str) -> String {
+        if let Some(register) = parse_register(target) {
+            return format!("${} = {}", register, self.vm.registers[register]);
+        }
+        let range = target.trim_start_matches("heap[").trim_end_matches(']');
+        if range.len() == target.len() {
+            return format!("cannot print `{}`", target);
+        }
+        let bounds: Vec<Option<usize>> = range.splitn(2, "..").map(parse_number).collect();
+        let (start, end) = match bounds[..] {
+            [Some(start), Some(end)] if start <= end => (start, end),
+            _ => return format!("`{}` is not a range", range),
+        };
+        let heap = self.vm.heap();
+        if end > heap.len() {
+            return format!("the heap is only {} bytes long", heap.len());
+        }
+        let lines: Vec<String> = heap[start..end]
+            .chunks(16)
+            .enumerate()
+            .map(|(i, chunk)| format!("{:04x}: {}", start + i * 16, hex(chunk)))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn current_opcode(&self) -> Option<Opcode> {
+        self.vm.program.get(self.vm.pc()).map(|byte| Opcode::from(*byte))
+    }
+
+    fn describe_offset(&self, offset: usize) -> String {
+        match self.symbols.label_at(offset as u32) {
+            Some(label) => format!("{:04x} <{}>", offset, label),
+            None => format!("{:04x}", offset),
+        }
+    }
+
+    fn location(&self) -> String {
+        let pc = self.vm.pc();
+        let end = (pc + 4).min(self.vm.program.len());
+        match self.current_opcode() {
+            Some(opcode) => format!("{}: {} ({})", self.describe_offset(pc), opcode.mnemonic(), hex(&self.vm.program[pc..end])),
+            None => format!("{}: end of program", self.describe_offset(pc)),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(digits) => usize::from_str_radix(digits, 16).ok(),
+        None => text.parse::<usize>().ok(),
+    }
+}
+
+fn parse_register(text: &str) -> Option<usize> {
+    match text.strip_prefix('$').map(|digits| digits.parse::<usize>()) {
+        Some(Ok(register)) if register < 32 => Some(register),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    fn debugger(source: &str) -> Debugger {
+        let mut asm = Assembler::new();
+        let image = asm.assemble(source).unwrap();
+        Debugger::new(image, asm.symbols).unwrap()
+    }
+
+    const PROGRAM: &str = r"
+        .data
+        .code
+        load $0 #3
+        load $1 #8
+        aloc $1
+        loop: dec $0
+        call @store
+        eq $0 $2
+        djmpne @loop
+        hlt
+        store: sb $0 $0 #0
+        ret
+    ";
+
+    #[test]
+    fn test_step_and_next() {
+        let mut d = debugger(PROGRAM);
+        assert_eq!(d.execute_command("step"), "0044: load (00 01 00 08)");
+        d.execute_command("step");
+        d.execute_command("step");
+        assert_eq!(d.execute_command("step"), "0050: call (1a 00 60 00)");
+        assert_eq!(d.execute_command("next"), "0054: eq (09 00 02 ff)");
+        assert_eq!(d.execute_command("step"), "0058: djmpne (20 00 4c 00)");
+        assert_eq!(d.execute_command("step"), "004c <loop>: dec (13 00 00 00)");
+        d.execute_command("step");
+        assert_eq!(d.execute_command("step"), "0060 <store>: sb (18 00 00 00)");
+    }
+
+    #[test]
+    fn test_breakpoints_and_watches() {
+        let mut d = debugger(PROGRAM);
+        assert_eq!(d.execute_command("break store"), "breakpoint at 0060 <store>");
+        assert_eq!(d.execute_command("continue"), "breakpoint hit\n0060 <store>: sb (18 00 00 00)");
+        assert_eq!(d.execute_command("print $0"), "$0 = 2");
+        assert_eq!(d.execute_command("break nowhere"), "no label named `nowhere`");
+
+        let mut d = debugger(PROGRAM);
+        d.execute_command("watch $0");
+        assert_eq!(d.execute_command("c"), "$0 changed from 0 to 3\n0044: load (00 01 00 08)");
+        assert_eq!(d.execute_command("c"), "$0 changed from 3 to 2\n0050: call (1a 00 60 00)");
+    }
+
+    #[test]
+    fn test_breakpoint_at_entry_point() {
+        let mut d = debugger(PROGRAM);
+        assert_eq!(d.execute_command("break 0x40"), "breakpoint at 0040");
+        assert_eq!(d.execute_command("continue"), "breakpoint hit\n0040: load (00 00 00 03)");
+        assert_eq!(d.execute_command("continue"), "the program halted");
+    }
+
+    #[test]
+    fn test_budget_exhausted_can_resume() {
+        let mut d = debugger(PROGRAM);
+        d.vm.budget = Some(3);
+        assert_eq!(d.execute_command("continue"), "the instruction budget ran out; use `budget` to add more\n0048: aloc (11 01 00 00)");
+        assert_eq!(d.execute_command("budget 100"), "the instruction budget is now 101");
+        assert_eq!(d.execute_command("budget lots"), "`lots` is not a number");
+        assert_eq!(d.execute_command("continue"), "the program halted");
+    }
+
+    #[test]
+    fn test_print_heap() {
+        let mut d = debugger(PROGRAM);
+        d.execute_command("break 0x5c");
+        d.execute_command("continue");
+        assert_eq!(d.execute_command("print heap[0..8]"), "0000: 00 01 02 00 00 00 00 00");
+        assert_eq!(d.execute_command("print heap[0..9]"), "the heap is only 8 bytes long");
+        assert_eq!(d.execute_command("print heap[2..1]"), "`2..1` is not a range");
+    }
+
+    #[test]
+    fn test_program_end() {
+        let mut d = debugger(PROGRAM);
+        assert_eq!(d.execute_command("continue"), "the program halted");
+        assert_eq!(d.execute_command("step"), "the program has finished");
+        assert_eq!(d.execute_command("jump"), "unknown command `jump`; try `help`");
+
+        let mut d = debugger(".data\n.code\nload $0 #1\n");
+        assert_eq!(d.execute_command("continue"), "the program ran past its last instruction");
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use instruction::{Opcode, OperandKind, NO_REGISTER};
+use pie::{PieError, PieHeader};
+
+const INSTRUCTION_LENGTH: usize = 4;
+
+/// Turns a PIE image back into assembly that `Assembler::assemble` accepts.
+///
+/// Every line is annotated with the image offset and raw bytes it came
+/// from. Strings in the read-only section become `.asciiz` directives, and
+/// jump and call targets get synthesized labels so the listing reassembles
+/// to the same image.
+pub fn disassemble(image: &[u8]) -> Result<String, PieError> {
+    let header = PieHeader::parse(image)?;
+    let disassembler = Disassembler::new(image, &header);
+    Ok(disassembler.render())
+}
+
+struct Disassembler<'a> {
+    header: &'a PieHeader,
+    ro: &'a [u8],
+    code: &'a [u8],
+    /// Read-only offsets that `loadf64` reads a constant from.
+    float_offsets: HashSet<usize>,
+    /// Names of the strings in the read-only section, by offset.
+    string_labels: HashMap<usize, String>,
+    /// Image offsets of instructions that something jumps to.
+    code_labels: BTreeSet<usize>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn new(image: &'a [u8], header: &'a PieHeader) -> Disassembler<'a> {
+        let mut disassembler = Disassembler {
+            header,
+            ro: &image[header.ro.start()..header.ro.end()],
+            code: &image[header.code.start()..header.code.end()],
+            float_offsets: HashSet::new(),
+            string_labels: HashMap::new(),
+            code_labels: BTreeSet::new(),
+        };
+        disassembler.find_references();
+        disassembler
+    }
+
+    fn find_references(&mut self) {
+        for chunk in self.code.chunks(INSTRUCTION_LENGTH) {
+            if chunk.len() < INSTRUCTION_LENGTH {
+                break;
+            }
+            let operand = BigEndian::read_u16(&chunk[1..3]) as usize;
+            match Opcode::from(chunk[0]) {
+                Opcode::LOADF64 => {
+                    self.float_offsets.insert(BigEndian::read_u16(&chunk[2..4]) as usize);
+                },
+                Opcode::DJMP | Opcode::DJMPE | Opcode::DJMPNE | Opcode::CALL if self.is_instruction_start(operand) => {
+                    self.code_labels.insert(operand);
+                },
+                _ => {},
+            }
+        }
+        if self.has_entry_directive() {
+            self.code_labels.insert(self.header.entry_point as usize);
+        }
+
+        for (offset, _) in self.strings() {
+            self.string_labels.insert(offset, format!("s{:04x}", offset));
+        }
+    }
+
+    fn is_instruction_start(&self, offset: usize) -> bool {
+        offset >= self.header.code.start() &&
+            offset < self.header.code.end() &&
+            (offset - self.header.code.start()).is_multiple_of(INSTRUCTION_LENGTH)
+    }
+
+    // Without an `.entry` directive the assembler starts at the first
+    // instruction, so one is only needed when the entry point is elsewhere.
+    fn has_entry_directive(&self) -> bool {
+        self.header.entry_point as usize != self.header.code.start() &&
+            self.is_instruction_start(self.header.entry_point as usize)
+    }
+
+    // The read-only section holds NUL-terminated strings followed by the
+    // 8-byte float constants that `loadf64` reads.
+    fn strings(&self) -> Vec<(usize, &'a [u8])> {
+        let mut strings = vec![];
+        let mut offset = 0;
+        while offset < self.ro.len() {
+            if self.float_offsets.contains(&offset) && offset + 8 <= self.ro.len() {
+                offset += 8;
+                continue;
+            }
+            match self.ro[offset..].iter().position(|b| *b == 0) {
+                Some(length) => {
+                    strings.push((offset, &self.ro[offset..offset + length]));
+                    offset += length + 1;
+                },
+                None => break,
+            }
+        }
+        strings
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!(
+            "; PIE version {}, entry point {:04x}\n; read-only {:04x}..{:04x}, code {:04x}..{:04x}\n",
+            self.header.version, self.header.entry_point,
+            self.header.ro.start(), self.header.ro.end(),
+            self.header.code.start(), self.header.code.end(),
+        ));
+
+        out.push_str(".data\n");
+        if self.has_entry_directive() {
+            out.push_str(&format!("{:<8}.entry {}\n", "", code_label(self.header.entry_point as usize)));
+        }
+        self.render_ro(&mut out);
+
+        out.push_str(".code\n");
+        for (i, chunk) in self.code.chunks(INSTRUCTION_LENGTH).enumerate() {
+            let offset = self.header.code.start() + i * INSTRUCTION_LENGTH;
+            out.push_str(&self.render_instruction(offset, chunk));
+        }
+        out
+    }
+
+    fn render_ro(&self, out: &mut String) {
+        let mut offset = 0;
+        for (start, bytes) in self.strings() {
+            self.render_floats(out, offset, start);
+            let image_offset = self.header.ro.start() + start;
+            match std::str::from_utf8(bytes) {
+                Ok(s) if !s.contains('\'') => {
+                    let label = format!("{}:", self.string_labels[&start]);
+                    let line = format!("{:<8}.asciiz '{}'", label, s);
+                    out.push_str(&format!("{:<32}; {:04x}\n", line, image_offset));
+                },
+                _ => {
+                    out.push_str(&format!("; {:04x}: string that cannot be written as .asciiz: {}\n", image_offset, hex(bytes)));
+                },
+            }
+            offset = start + bytes.len() + 1;
+        }
+        self.render_floats(out, offset, self.ro.len());
+    }
+
+    // Float constants are not written out, since `loadf64` puts them back.
+    fn render_floats(&self, out: &mut String, from: usize, to: usize) {
+        let mut offset = from;
+        while offset < to {
+            let image_offset = self.header.ro.start() + offset;
+            if self.float_offsets.contains(&offset) && offset + 8 <= to {
+                let value = BigEndian::read_f64(&self.ro[offset..offset + 8]);
+                let text = float_literal(value).unwrap_or_else(|| format!("{:?}", value));
+                out.push_str(&format!("; {:04x}: f64 {}\n", image_offset, text));
+                offset += 8;
+            } else {
+                out.push_str(&format!("; {:04x}: {}\n", image_offset, hex(&self.ro[offset..to])));
+                offset = to;
+            }
+        }
+    }
+
+    fn render_instruction(&self, offset: usize, bytes: &[u8]) -> String {
+        if bytes.len() < INSTRUCTION_LENGTH {
+            return format!("; {:04x}: truncated instruction {}\n", offset, hex(bytes));
+        }
+        let opcode = Opcode::from(bytes[0]);
+        if opcode == Opcode::IGL {
+            return format!("; {:04x}: illegal opcode {}\n", offset, hex(bytes));
+        }
+
+        let mut text = opcode.mnemonic();
+        let mut position = 1;
+        for kind in opcode.signature() {
+            let operand = match kind {
+                OperandKind::Register => Some(format!("${}", bytes[position])),
+                OperandKind::OptionalRegister if bytes[position] == NO_REGISTER => None,
+                OperandKind::OptionalRegister => Some(format!("${}", bytes[position])),
+                OperandKind::Integer8 => Some(format!("#{}", bytes[position])),
+                OperandKind::Integer16 => Some(format!("#{}", BigEndian::read_u16(&bytes[position..]))),
+                OperandKind::Address => Some(self.render_address(opcode, BigEndian::read_u16(&bytes[position..]) as usize)),
+                OperandKind::Float => {
+                    let constant = BigEndian::read_u16(&bytes[position..]) as usize;
+                    if constant + 8 > self.ro.len() {
+                        return format!("; {:04x}: float constant out of bounds {}\n", offset, hex(bytes));
+                    }
+                    match float_literal(BigEndian::read_f64(&self.ro[constant..constant + 8])) {
+                        Some(literal) => Some(format!("#{}", literal)),
+                        None => return format!("; {:04x}: float constant that cannot be written {}\n", offset, hex(bytes)),
+                    }
+                },
+            };
+            if let Some(operand) = operand {
+                text.push(' ');
+                text.push_str(&operand);
+            }
+            position += match kind {
+                OperandKind::Integer16 | OperandKind::Address | OperandKind::Float => 2,
+                _ => 1,
+            };
+        }
+
+        let label = if self.code_labels.contains(&offset) {
+            format!("{}:", code_label(offset))
+        } else {
+            String::new()
+        };
+        let line = format!("{:<8}{}", label, text);
+        format!("{:<32}; {:04x}: {}\n", line, offset, hex(bytes))
+    }
+
+    fn render_address(&self, opcode: Opcode, address: usize) -> String {
+        if opcode == Opcode::PRTS {
+            if let Some(label) = self.string_labels.get(&address) {
+                return format!("@{}", label);
+            }
+        } else if self.code_labels.contains(&address) {
+            return format!("@{}", code_label(address));
+        }
+        format!("#{}", address)
+    }
+}
+
+// Writes a float the way float operands are written in source: plain
+// `digits.digits` with no exponent, or `inf`, `-inf` or `nan`. A NaN with a
+// payload has no such form.
+fn float_literal(value: f64) -> Option<String> {
+    if value.is_nan() {
+        return if value.to_bits() == f64::NAN.to_bits() { Some("nan".to_string()) } else { None };
+    }
+    if value.is_infinite() {
+        return Some(if value > 0.0 { "inf" } else { "-inf" }.to_string());
+    }
+    let text = value.to_string();
+    Some(if text.contains('.') { text } else { format!("{}.0", text) })
+}
+
+fn code_label(offset: usize) -> String {
+    format!("L{:04x}", offset)
+}
+
+/// Formats bytes as space-separated hex pairs.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use pie::PIE_HEADER_LENGTH;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        Assembler::new().assemble(source).unwrap()
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let image = assemble(r"
+            .data
+            hello: .asciiz 'Hello'
+            .code
+            loop: prts @hello
+            load $0 #100
+            eq $0 $1
+            lw $1 $2 #4
+            djmpe @loop
+            hlt
+        ");
+        let listing = disassemble(&image).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[2], ".data");
+        assert_eq!(lines[3], "s0000:  .asciiz 'Hello'         ; 0040");
+        assert_eq!(lines[4], ".code");
+        assert_eq!(lines[5], "L0046:  prts @s0000             ; 0046: 15 00 00 00");
+        assert_eq!(lines[6], "        load $0 #100            ; 004a: 00 00 00 64");
+        assert_eq!(lines[7], "        eq $0 $1                ; 004e: 09 00 01 ff");
+        assert_eq!(lines[8], "        lw $1 $2 #4             ; 0052: 17 01 02 04");
+        assert_eq!(lines[9], "        djmpe @L0046            ; 0056: 14 00 46 00");
+        assert_eq!(lines[10], "        hlt                     ; 005a: 05 00 00 00");
+    }
+
+    #[test]
+    fn test_disassemble_round_trip() {
+        let image = assemble(r"
+            .data
+            .entry main
+            greeting: .asciiz 'Hi there'
+            empty: .asciiz ''
+            .code
+            helper: loadf64 $0 #1.5
+            loadf64 $1 #-2.25
+            addf64 $0 $1 $2
+            ret
+            main: call @helper
+            prts @greeting
+            jmpfe #4
+            hlt
+        ");
+        let listing = disassemble(&image).unwrap();
+        assert!(listing.contains(".entry L"));
+        assert!(listing.contains("; 004a: f64 1.5"));
+        assert_eq!(assemble(&listing), image);
+    }
+
+    #[test]
+    fn test_disassemble_extreme_floats() {
+        let mut source = ".data\n.code\n".to_string();
+        for (register, value) in [1e300, 1e-7, -2.5e-300, 3.0, f64::INFINITY, f64::NEG_INFINITY, f64::NAN].iter().enumerate() {
+            source.push_str(&format!("loadf64 ${} #{}\n", register, float_literal(*value).unwrap()));
+        }
+        let image = assemble(&source);
+        let listing = disassemble(&image).unwrap();
+        assert!(listing.contains("loadf64 $3 #3.0"));
+        assert!(listing.contains("loadf64 $6 #nan"));
+        assert!(!listing.contains("e-"));
+        assert_eq!(assemble(&listing), image);
+    }
+
+    #[test]
+    fn test_disassemble_rejects_bad_image() {
+        assert_eq!(disassemble(&[0; 10]), Err(PieError::TooShort { length: 10 }));
+
+        let mut image = assemble(".data\n.code\nhlt\n");
+        image[PIE_HEADER_LENGTH] = 0xaa;
+        assert!(disassemble(&image).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_illegal_opcode() {
+        let body = [200, 0, 0, 0];
+        let mut image = PieHeader::new(&body, 0, PIE_HEADER_LENGTH as u32).to_bytes();
+        image.extend_from_slice(&body);
+        assert!(disassemble(&image).unwrap().contains("; 0040: illegal opcode c8 00 00 00"));
+    }
+}
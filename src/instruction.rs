@@ -1,5 +1,11 @@
+use std::fmt;
+
 use nom::types::CompleteStr;
 
+/// Written in place of a register when an optional register operand is
+/// omitted, e.g. the destination of `eq $0 $1`.
+pub const NO_REGISTER: u8 = 0xff;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Opcode {
     LOAD, // 0
@@ -22,11 +28,114 @@ pub enum Opcode {
     ALOC,
     INC,
     DEC,
-    // DJMPE
+    DJMPE, // 20
     PRTS,
+    LB,
+    LW,
+    SB,
+    SW, // 25
+    CALL,
+    RET,
+    PUSH,
+    POP,
+    JMPNE, // 30
+    DJMP,
+    DJMPNE,
+    JMPFE,
+    JMPBE,
+    AND, // 35
+    OR,
+    XOR,
+    NOT,
+    SHL,
+    SHR, // 40
+    MOD,
+    LOADF64,
+    ADDF64,
+    SUBF64,
+    MULF64, // 45
+    DIVF64,
+    EQF64,
+    NEQF64,
+    GTF64,
+    GTEF64, // 50
+    LTF64,
+    LTEF64,
+    ITOF,
+    FTOI,
+    LOADHI, // 55
+    EXIT,
+    SYSCALL,
     IGL,
 }
 
+impl Opcode {
+    /// Comparisons always set the equal flag and can also store their
+    /// result as 0 or 1 in an optional destination register.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            Opcode::EQ | Opcode::NEQ | Opcode::GT | Opcode::LT | Opcode::GTE | Opcode::LTE |
+            Opcode::EQF64 | Opcode::NEQF64 | Opcode::GTF64 | Opcode::GTEF64 | Opcode::LTF64 | Opcode::LTEF64
+        )
+    }
+
+    /// The name the assembler knows the opcode by.
+    pub fn mnemonic(self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    /// The operands the instruction takes, in the order they are encoded.
+    pub fn signature(self) -> &'static [OperandKind] {
+        use self::OperandKind::*;
+
+        if self.is_comparison() {
+            return &[Register, Register, OptionalRegister];
+        }
+        match self {
+            Opcode::LOAD | Opcode::LOADHI => &[Register, Integer16],
+            Opcode::ADD | Opcode::SUB | Opcode::MUL | Opcode::DIV |
+            Opcode::AND | Opcode::OR | Opcode::XOR | Opcode::SHL | Opcode::SHR | Opcode::MOD |
+            Opcode::ADDF64 | Opcode::SUBF64 | Opcode::MULF64 | Opcode::DIVF64 => &[Register, Register, Register],
+            Opcode::JMP | Opcode::JMPF | Opcode::JMPB | Opcode::JMPE | Opcode::JMPNE |
+            Opcode::ALOC | Opcode::INC | Opcode::DEC | Opcode::PUSH | Opcode::POP | Opcode::EXIT => &[Register],
+            Opcode::DJMP | Opcode::DJMPE | Opcode::DJMPNE | Opcode::CALL | Opcode::PRTS => &[Address],
+            Opcode::JMPFE | Opcode::JMPBE | Opcode::SYSCALL => &[Integer16],
+            Opcode::LB | Opcode::LW | Opcode::SB | Opcode::SW => &[Register, Register, Integer8],
+            Opcode::NOT | Opcode::ITOF | Opcode::FTOI => &[Register, Register],
+            Opcode::LOADF64 => &[Register, Float],
+            _ => &[],
+        }
+    }
+}
+
+/// What an opcode expects in one of its operand slots.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OperandKind {
+    Register,
+    /// A register that may be left out; see `NO_REGISTER`.
+    OptionalRegister,
+    Integer8,
+    Integer16,
+    /// A label, or an absolute offset given as a 16-bit integer.
+    Address,
+    /// A float literal, stored in the read-only section.
+    Float,
+}
+
+impl fmt::Display for OperandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperandKind::Register => write!(f, "register"),
+            OperandKind::OptionalRegister => write!(f, "[register]"),
+            OperandKind::Integer8 => write!(f, "imm8"),
+            OperandKind::Integer16 => write!(f, "imm16"),
+            OperandKind::Address => write!(f, "label/imm16"),
+            OperandKind::Float => write!(f, "float"),
+        }
+    }
+}
+
 impl From<u8> for Opcode {
     fn from(v: u8) -> Self {
         match v {
@@ -49,7 +158,44 @@ impl From<u8> for Opcode {
             17 => Opcode::ALOC,
             18 => Opcode::INC,
             19 => Opcode::DEC,
+            20 => Opcode::DJMPE,
             21 => Opcode::PRTS,
+            22 => Opcode::LB,
+            23 => Opcode::LW,
+            24 => Opcode::SB,
+            25 => Opcode::SW,
+            26 => Opcode::CALL,
+            27 => Opcode::RET,
+            28 => Opcode::PUSH,
+            29 => Opcode::POP,
+            30 => Opcode::JMPNE,
+            31 => Opcode::DJMP,
+            32 => Opcode::DJMPNE,
+            33 => Opcode::JMPFE,
+            34 => Opcode::JMPBE,
+            35 => Opcode::AND,
+            36 => Opcode::OR,
+            37 => Opcode::XOR,
+            38 => Opcode::NOT,
+            39 => Opcode::SHL,
+            40 => Opcode::SHR,
+            41 => Opcode::MOD,
+            42 => Opcode::LOADF64,
+            43 => Opcode::ADDF64,
+            44 => Opcode::SUBF64,
+            45 => Opcode::MULF64,
+            46 => Opcode::DIVF64,
+            47 => Opcode::EQF64,
+            48 => Opcode::NEQF64,
+            49 => Opcode::GTF64,
+            50 => Opcode::GTEF64,
+            51 => Opcode::LTF64,
+            52 => Opcode::LTEF64,
+            53 => Opcode::ITOF,
+            54 => Opcode::FTOI,
+            55 => Opcode::LOADHI,
+            56 => Opcode::EXIT,
+            57 => Opcode::SYSCALL,
             _ => Opcode::IGL
         }
     }
@@ -77,7 +223,44 @@ impl From<Opcode> for u8 {
             Opcode::ALOC => 17,
             Opcode::INC => 18,
             Opcode::DEC => 19,
+            Opcode::DJMPE => 20,
             Opcode::PRTS => 21,
+            Opcode::LB => 22,
+            Opcode::LW => 23,
+            Opcode::SB => 24,
+            Opcode::SW => 25,
+            Opcode::CALL => 26,
+            Opcode::RET => 27,
+            Opcode::PUSH => 28,
+            Opcode::POP => 29,
+            Opcode::JMPNE => 30,
+            Opcode::DJMP => 31,
+            Opcode::DJMPNE => 32,
+            Opcode::JMPFE => 33,
+            Opcode::JMPBE => 34,
+            Opcode::AND => 35,
+            Opcode::OR => 36,
+            Opcode::XOR => 37,
+            Opcode::NOT => 38,
+            Opcode::SHL => 39,
+            Opcode::SHR => 40,
+            Opcode::MOD => 41,
+            Opcode::LOADF64 => 42,
+            Opcode::ADDF64 => 43,
+            Opcode::SUBF64 => 44,
+            Opcode::MULF64 => 45,
+            Opcode::DIVF64 => 46,
+            Opcode::EQF64 => 47,
+            Opcode::NEQF64 => 48,
+            Opcode::GTF64 => 49,
+            Opcode::GTEF64 => 50,
+            Opcode::LTF64 => 51,
+            Opcode::LTEF64 => 52,
+            Opcode::ITOF => 53,
+            Opcode::FTOI => 54,
+            Opcode::LOADHI => 55,
+            Opcode::EXIT => 56,
+            Opcode::SYSCALL => 57,
             Opcode::IGL => 100,
         }
     }
@@ -113,7 +296,44 @@ impl<'a> From<&'a str> for Opcode {
             "aloc" => Opcode::ALOC,
             "inc" => Opcode::INC,
             "dec" => Opcode::DEC,
+            "djmpe" => Opcode::DJMPE,
             "prts" => Opcode::PRTS,
+            "lb" => Opcode::LB,
+            "lw" => Opcode::LW,
+            "sb" => Opcode::SB,
+            "sw" => Opcode::SW,
+            "call" => Opcode::CALL,
+            "ret" => Opcode::RET,
+            "push" => Opcode::PUSH,
+            "pop" => Opcode::POP,
+            "jmpne" => Opcode::JMPNE,
+            "djmp" => Opcode::DJMP,
+            "djmpne" => Opcode::DJMPNE,
+            "jmpfe" => Opcode::JMPFE,
+            "jmpbe" => Opcode::JMPBE,
+            "and" => Opcode::AND,
+            "or" => Opcode::OR,
+            "xor" => Opcode::XOR,
+            "not" => Opcode::NOT,
+            "shl" => Opcode::SHL,
+            "shr" => Opcode::SHR,
+            "mod" => Opcode::MOD,
+            "loadf64" => Opcode::LOADF64,
+            "addf64" => Opcode::ADDF64,
+            "subf64" => Opcode::SUBF64,
+            "mulf64" => Opcode::MULF64,
+            "divf64" => Opcode::DIVF64,
+            "eqf64" => Opcode::EQF64,
+            "neqf64" => Opcode::NEQF64,
+            "gtf64" => Opcode::GTF64,
+            "gtef64" => Opcode::GTEF64,
+            "ltf64" => Opcode::LTF64,
+            "ltef64" => Opcode::LTEF64,
+            "itof" => Opcode::ITOF,
+            "ftoi" => Opcode::FTOI,
+            "loadhi" => Opcode::LOADHI,
+            "exit" => Opcode::EXIT,
+            "syscall" => Opcode::SYSCALL,
             _ => Opcode::IGL
         }
     }
@@ -127,7 +347,7 @@ pub struct Instruction {
 impl Instruction {
     pub fn new(opcode: Opcode) -> Instruction {
         Instruction {
-            opcode: opcode
+            opcode
         }
     }
 }
@@ -155,4 +375,30 @@ mod tests {
         let opcode = Opcode::from(CompleteStr("illegal"));
         assert_eq!(opcode, Opcode::IGL);
     }
+
+    #[test]
+    fn test_opcode_round_trip() {
+        for byte in 0..=255u8 {
+            let opcode = Opcode::from(byte);
+            if opcode != Opcode::IGL {
+                assert_eq!(u8::from(opcode), byte);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mnemonic_round_trip() {
+        for byte in 0..=255u8 {
+            let opcode = Opcode::from(byte);
+            assert_eq!(Opcode::from(&opcode.mnemonic()[..]), opcode);
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(Opcode::HLT.signature(), &[]);
+        assert_eq!(Opcode::LOAD.signature(), &[OperandKind::Register, OperandKind::Integer16]);
+        assert_eq!(Opcode::EQ.signature().last(), Some(&OperandKind::OptionalRegister));
+        assert_eq!(Opcode::PRTS.signature(), &[OperandKind::Address]);
+    }
 }
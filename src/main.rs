@@ -1,39 +1,186 @@
 use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 extern crate clap;
 #[macro_use]
 extern crate nom;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+extern crate env_logger;
 
-use clap::App;
+use clap::{App, ErrorKind};
 
 pub mod instruction;
+pub mod pie;
 pub mod vm;
 pub mod repl;
 pub mod assembler;
+pub mod disassembler;
+pub mod debugger;
+
+// A program's own `exit` status is passed through when it is at most
+// `MAX_GUEST_STATUS`. `iridium` uses the statuses just above it for its own
+// errors, and stays below 126, since shells use 126 and up for commands
+// that could not run or were killed by a signal.
+
+/// The highest `exit` status passed through unchanged.
+const MAX_GUEST_STATUS: i32 = 99;
+/// Exit status when the program exits with a status above
+/// `MAX_GUEST_STATUS` or below 0.
+const EXIT_GUEST_STATUS_OUT_OF_RANGE: i32 = 100;
+/// Exit status for bad arguments, unreadable or unwritable files, and
+/// images that `disasm` or `debug` cannot load.
+const EXIT_IO_ERROR: i32 = 101;
+/// Exit status when the source does not assemble.
+const EXIT_ASSEMBLY_ERROR: i32 = 102;
+/// Exit status when the program traps, including on an illegal opcode or a
+/// malformed image.
+const EXIT_TRAP: i32 = 103;
+/// Exit status when `--budget` runs out before the program finishes.
+const EXIT_BUDGET_EXHAUSTED: i32 = 104;
+
+use assembler::symbols::SymbolTable;
+use pie::PIE_HEADER_PREFIX;
+use vm::trace::{TraceFormat, TraceWriter};
+use vm::vm_errors::{ExitReason, VmError};
 
 fn main() {
+    env_logger::init();
     let yaml = load_yaml!("cli.yaml");
-    let matches = App::from_yaml(yaml).get_matches();
-    let target_file = matches.value_of("INPUT_FILE");
-    match target_file {
-        Some(filename) => {
-            let program = read_file(filename);
-            let mut asm = assembler::Assembler::new();
-            let mut vm = vm::VM::new();
-            let program = asm.assemble(&program);
-            match program {
-                Ok(p) => {
-                    vm.add_bytes(p);
-                    vm.run();
-                    std::process::exit(0);
+    let matches = match App::from_yaml(yaml).get_matches_safe() {
+        Ok(matches) => matches,
+        Err(e) => match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                std::process::exit(EXIT_IO_ERROR);
+            },
+        },
+    };
+    match matches.subcommand() {
+        ("run", Some(matches)) => {
+            let budget = matches.value_of("BUDGET").map(|budget| match budget.parse::<u64>() {
+                Ok(budget) => budget,
+                Err(_) => {
+                    eprintln!("error: `{}` is not a valid budget", budget);
+                    std::process::exit(EXIT_IO_ERROR);
                 },
-                Err(_e) => {},
-            }
+            });
+            let trace = matches.value_of("TRACE").map(|path| {
+                let format = match matches.value_of("TRACE_FORMAT") {
+                    Some("binary") => TraceFormat::Binary,
+                    _ => TraceFormat::JsonLines,
+                };
+                (path, format)
+            });
+            run(matches.value_of("INPUT_FILE").unwrap(), budget, trace)
+        },
+        ("build", Some(matches)) => build(matches.value_of("INPUT_FILE").unwrap(), matches.value_of("OUTPUT")),
+        ("disasm", Some(matches)) => disasm(matches.value_of("INPUT_FILE").unwrap()),
+        ("debug", Some(matches)) => debug(matches.value_of("INPUT_FILE").unwrap()),
+        _ => start_repl(),
+    }
+}
+
+/// Runs a prebuilt `.pie` image, or assembles `.iasm` source and runs it,
+/// optionally tracing every instruction to a file.
+fn run(filename: &str, budget: Option<u64>, trace: Option<(&str, TraceFormat)>) {
+    let image = load_image(filename);
+    let mut vm = vm::VM::new();
+    vm.add_bytes(image);
+    vm.budget = budget;
+    if let Some((path, format)) = trace {
+        match File::create(path) {
+            Ok(file) => vm.tracer = Some(Box::new(TraceWriter::new(BufWriter::new(file), format))),
+            Err(e) => {
+                eprintln!("error: unable to write {}: {}", path, e);
+                std::process::exit(EXIT_IO_ERROR);
+            },
+        }
+    }
+    let result = vm.run();
+    std::process::exit(finish_run(&mut vm, result));
+}
+
+/// Flushes the trace, reports how the program stopped and returns the status
+/// `run` exits with. A trace that could not be written is an I/O error even
+/// when the program itself succeeded, since the trace has been lost.
+fn finish_run(vm: &mut vm::VM, result: Result<ExitReason, VmError>) -> i32 {
+    let mut trace_failed = false;
+    if let Some(mut tracer) = vm.tracer.take() {
+        if let Err(e) = tracer.finish() {
+            eprintln!("error: unable to write the trace: {}", e);
+            trace_failed = true;
+        }
+    }
+    let status = match result {
+        Ok(ExitReason::Exit(code)) if !(0..=MAX_GUEST_STATUS).contains(&code) => {
+            eprintln!("error: the program exited with status {}, which is outside 0..={}", code, MAX_GUEST_STATUS);
+            EXIT_GUEST_STATUS_OUT_OF_RANGE
+        },
+        Ok(ExitReason::Exit(code)) => code,
+        Ok(ExitReason::BudgetExhausted) => {
+            eprintln!("error: instruction budget exhausted at pc {}", vm.pc());
+            EXIT_BUDGET_EXHAUSTED
+        },
+        Ok(_) => 0,
+        Err(e @ VmError::TraceFailed { .. }) => {
+            eprintln!("error: {}", e);
+            EXIT_IO_ERROR
+        },
+        Err(e) => {
+            eprintln!("error: {}", e);
+            EXIT_TRAP
+        },
+    };
+    if trace_failed {
+        EXIT_IO_ERROR
+    } else {
+        status
+    }
+}
+
+/// Assembles `filename` and writes the image next to it, or to `output`.
+fn build(filename: &str, output: Option<&str>) {
+    let (image, _) = assemble(filename, read_bytes(filename));
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(filename).with_extension("pie"),
+    };
+    if let Err(e) = std::fs::write(&output, image) {
+        eprintln!("error: unable to write {}: {}", output.display(), e);
+        std::process::exit(EXIT_IO_ERROR);
+    }
+}
+
+fn disasm(filename: &str) {
+    match disassembler::disassemble(&read_bytes(filename)) {
+        Ok(listing) => print!("{}", listing),
+        Err(e) => {
+            eprintln!("error: unable to disassemble {}: {}", filename, e);
+            std::process::exit(EXIT_IO_ERROR);
+        },
+    }
+}
+
+/// Runs a program under the debugger. Labels are only available when it is
+/// given source rather than a prebuilt image.
+fn debug(filename: &str) {
+    let bytes = read_bytes(filename);
+    let (image, symbols) = if bytes.starts_with(&PIE_HEADER_PREFIX) {
+        (bytes, SymbolTable::new())
+    } else {
+        assemble(filename, bytes)
+    };
+    match debugger::Debugger::new(image, symbols) {
+        Ok(mut debugger) => debugger.run(),
+        Err(e) => {
+            eprintln!("error: unable to load {}: {}", filename, e);
+            std::process::exit(EXIT_IO_ERROR);
         },
-        None => start_repl(),
     }
 }
 
@@ -42,24 +189,105 @@ fn start_repl() {
     repl.run();
 }
 
-fn read_file(filename: &str) -> String {
-    let filepath = Path::new(filename);
-    match File::open(Path::new(&filepath)) {
-        Ok(mut fh) => {
-            let mut contents = String::new();
-            match fh.read_to_string(&mut contents) {
-                Ok(_) => {
-                    return contents;
-                },
-                Err(e) => {
-                    println!("There was an error reading file: {:?}", e);
-                    std::process::exit(1);
-                },
-            }
+fn read_bytes(filename: &str) -> Vec<u8> {
+    match std::fs::read(filename) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("error: unable to read {}: {}", filename, e);
+            std::process::exit(EXIT_IO_ERROR);
         },
+    }
+}
+
+// Anything that does not start with the PIE magic number is treated as
+// assembly source.
+fn load_image(filename: &str) -> Vec<u8> {
+    let bytes = read_bytes(filename);
+    if bytes.starts_with(&PIE_HEADER_PREFIX) {
+        return bytes;
+    }
+    assemble(filename, bytes).0
+}
+
+fn assemble(filename: &str, bytes: Vec<u8>) -> (Vec<u8>, SymbolTable) {
+    let source = match String::from_utf8(bytes) {
+        Ok(source) => source,
         Err(e) => {
-            println!("File not found: {:?}", e);
-            std::process::exit(1);
+            eprintln!("error: unable to read {}: {}", filename, e);
+            std::process::exit(EXIT_IO_ERROR);
         },
+    };
+    let mut asm = assembler::Assembler::with_file_name(filename);
+    match asm.assemble(&source) {
+        Ok(image) => (image, asm.symbols),
+        Err(errors) => {
+            for error in errors {
+                eprint!("{}", error.render(&source));
+            }
+            std::process::exit(EXIT_ASSEMBLY_ERROR);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use super::*;
+
+    // Accepts writes but fails to flush, like a full disk behind a
+    // `BufWriter`.
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::other("No space left on device"))
+        }
+    }
+
+    fn traced_vm(out: Box<dyn vm::trace::Tracer>) -> vm::VM {
+        let mut asm = assembler::Assembler::new();
+        let program = asm.assemble(".data\n.code\nload $0 #7\nexit $0\n").unwrap();
+        let mut vm = vm::VM::new();
+        vm.add_bytes(program);
+        vm.tracer = Some(out);
+        vm
+    }
+
+    #[test]
+    fn test_finish_run_passes_guest_status() {
+        let mut vm = traced_vm(Box::new(TraceWriter::new(vec![], TraceFormat::JsonLines)));
+        let result = vm.run();
+        assert_eq!(finish_run(&mut vm, result), 7);
+    }
+
+    #[test]
+    fn test_finish_run_rejects_guest_status_out_of_range() {
+        let mut asm = assembler::Assembler::new();
+        let program = asm.assemble(".data\n.code\nload $0 #127\nexit $0\n").unwrap();
+        let mut vm = vm::VM::new();
+        vm.add_bytes(program);
+        let result = vm.run();
+        assert_eq!(finish_run(&mut vm, result), EXIT_GUEST_STATUS_OUT_OF_RANGE);
+    }
+
+    #[test]
+    fn test_exit_statuses_avoid_reserved_ranges() {
+        let statuses = [EXIT_GUEST_STATUS_OUT_OF_RANGE, EXIT_IO_ERROR, EXIT_ASSEMBLY_ERROR, EXIT_TRAP, EXIT_BUDGET_EXHAUSTED];
+        for status in statuses.iter() {
+            assert!(*status > MAX_GUEST_STATUS && *status < 126);
+        }
+    }
+
+    #[test]
+    fn test_finish_run_reports_lost_trace() {
+        let mut vm = traced_vm(Box::new(TraceWriter::new(FullDisk, TraceFormat::JsonLines)));
+        let result = vm.run();
+        assert_eq!(result, Ok(ExitReason::Exit(7)));
+        assert_eq!(finish_run(&mut vm, result), EXIT_IO_ERROR);
     }
 }
@@ -0,0 +1,220 @@
+use std::fmt;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+pub const PIE_HEADER_PREFIX: [u8; 4] = [0x45, 0x50, 0x49, 0x45];
+pub const PIE_HEADER_LENGTH: usize = 64;
+pub const PIE_VERSION: u16 = 2;
+
+const VERSION_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 6;
+const ENTRY_POINT_OFFSET: usize = 8;
+const CODE_SECTION_OFFSET: usize = 12;
+const RO_SECTION_OFFSET: usize = 20;
+const DATA_SECTION_OFFSET: usize = 28;
+const CHECKSUM_OFFSET: usize = 36;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PieError {
+    TooShort { length: usize },
+    BadMagic,
+    UnsupportedVersion { found: u16, supported: u16 },
+    SectionOutOfBounds { section: &'static str },
+    EntryPointOutOfBounds { entry_point: u32 },
+    ChecksumMismatch { expected: u32, found: u32 },
+}
+
+impl fmt::Display for PieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PieError::TooShort { length } => write!(f, "image is {} bytes, shorter than the {} byte header", length, PIE_HEADER_LENGTH),
+            PieError::BadMagic => write!(f, "image does not start with the PIE magic number"),
+            PieError::UnsupportedVersion { found, supported } => write!(f, "PIE format version {} is not supported (expected {})", found, supported),
+            PieError::SectionOutOfBounds { section } => write!(f, "{} section extends past the end of the image", section),
+            PieError::EntryPointOutOfBounds { entry_point } => write!(f, "entry point {} is outside the code section", entry_point),
+            PieError::ChecksumMismatch { expected, found } => write!(f, "checksum mismatch: header says {:#010x}, image hashes to {:#010x}", expected, found),
+        }
+    }
+}
+
+/// A byte range of the image, measured from the start of the header.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PieSection {
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl PieSection {
+    pub fn new(offset: u32, length: u32) -> PieSection {
+        PieSection { offset, length }
+    }
+
+    pub fn start(&self) -> usize {
+        self.offset as usize
+    }
+
+    pub fn end(&self) -> usize {
+        self.offset as usize + self.length as usize
+    }
+
+    fn read(bytes: &[u8]) -> PieSection {
+        PieSection {
+            offset: LittleEndian::read_u32(&bytes[0..4]),
+            length: LittleEndian::read_u32(&bytes[4..8]),
+        }
+    }
+
+    fn write(&self, header: &mut Vec<u8>) {
+        header.write_u32::<LittleEndian>(self.offset).unwrap();
+        header.write_u32::<LittleEndian>(self.length).unwrap();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PieHeader {
+    pub version: u16,
+    pub flags: u16,
+    pub entry_point: u32,
+    pub code: PieSection,
+    pub ro: PieSection,
+    pub data: PieSection,
+    pub checksum: u32,
+}
+
+impl PieHeader {
+    /// Builds a header for an image whose body (everything after the header)
+    /// is `body`. The read-only section comes first, followed by the code.
+    pub fn new(body: &[u8], ro_length: u32, entry_point: u32) -> PieHeader {
+        let ro = PieSection::new(PIE_HEADER_LENGTH as u32, ro_length);
+        let code = PieSection::new(ro.end() as u32, body.len() as u32 - ro_length);
+        PieHeader {
+            version: PIE_VERSION,
+            flags: 0,
+            entry_point,
+            code,
+            ro,
+            data: PieSection::new(code.end() as u32, 0),
+            checksum: checksum(body),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend_from_slice(&PIE_HEADER_PREFIX);
+        header.write_u16::<LittleEndian>(self.version).unwrap();
+        header.write_u16::<LittleEndian>(self.flags).unwrap();
+        header.write_u32::<LittleEndian>(self.entry_point).unwrap();
+        self.code.write(&mut header);
+        self.ro.write(&mut header);
+        self.data.write(&mut header);
+        header.write_u32::<LittleEndian>(self.checksum).unwrap();
+        while header.len() < PIE_HEADER_LENGTH {
+            header.push(0);
+        }
+        header
+    }
+
+    /// Parses and validates the header at the start of `image`.
+    pub fn parse(image: &[u8]) -> Result<PieHeader, PieError> {
+        if image.len() < PIE_HEADER_LENGTH {
+            return Err(PieError::TooShort { length: image.len() });
+        }
+        if image[0..4] != PIE_HEADER_PREFIX {
+            return Err(PieError::BadMagic);
+        }
+
+        let version = LittleEndian::read_u16(&image[VERSION_OFFSET..]);
+        if version != PIE_VERSION {
+            return Err(PieError::UnsupportedVersion { found: version, supported: PIE_VERSION });
+        }
+
+        let header = PieHeader {
+            version,
+            flags: LittleEndian::read_u16(&image[FLAGS_OFFSET..]),
+            entry_point: LittleEndian::read_u32(&image[ENTRY_POINT_OFFSET..]),
+            code: PieSection::read(&image[CODE_SECTION_OFFSET..]),
+            ro: PieSection::read(&image[RO_SECTION_OFFSET..]),
+            data: PieSection::read(&image[DATA_SECTION_OFFSET..]),
+            checksum: LittleEndian::read_u32(&image[CHECKSUM_OFFSET..]),
+        };
+
+        for (name, section) in &[("code", header.code), ("read-only", header.ro), ("data", header.data)] {
+            if section.start() < PIE_HEADER_LENGTH || section.end() > image.len() {
+                return Err(PieError::SectionOutOfBounds { section: name });
+            }
+        }
+
+        let entry_point = header.entry_point as usize;
+        if entry_point < header.code.start() || entry_point > header.code.end() {
+            return Err(PieError::EntryPointOutOfBounds { entry_point: header.entry_point });
+        }
+
+        let found = checksum(&image[PIE_HEADER_LENGTH..]);
+        if found != header.checksum {
+            return Err(PieError::ChecksumMismatch { expected: header.checksum, found });
+        }
+
+        Ok(header)
+    }
+}
+
+/// FNV-1a hash of the image body.
+pub fn checksum(body: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in body {
+        hash ^= u32::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image() -> Vec<u8> {
+        let body = vec![72, 105, 0, 5, 0, 0, 0];
+        let header = PieHeader::new(&body, 3, PIE_HEADER_LENGTH as u32 + 3);
+        let mut image = header.to_bytes();
+        image.extend_from_slice(&body);
+        image
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let image = test_image();
+        assert_eq!(image.len(), PIE_HEADER_LENGTH + 7);
+        let header = PieHeader::parse(&image).unwrap();
+        assert_eq!(header.version, PIE_VERSION);
+        assert_eq!(header.ro, PieSection::new(64, 3));
+        assert_eq!(header.code, PieSection::new(67, 4));
+        assert_eq!(header.data, PieSection::new(71, 0));
+        assert_eq!(header.entry_point, 67);
+    }
+
+    #[test]
+    fn test_rejects_bad_magic() {
+        let mut image = test_image();
+        image[0] = 0;
+        assert_eq!(PieHeader::parse(&image), Err(PieError::BadMagic));
+        assert_eq!(PieHeader::parse(&image[0..10]), Err(PieError::TooShort { length: 10 }));
+    }
+
+    #[test]
+    fn test_rejects_unsupported_version() {
+        let mut image = test_image();
+        image[VERSION_OFFSET] = 9;
+        assert_eq!(PieHeader::parse(&image), Err(PieError::UnsupportedVersion { found: 9, supported: PIE_VERSION }));
+    }
+
+    #[test]
+    fn test_rejects_corrupt_body() {
+        let mut image = test_image();
+        let last = image.len() - 1;
+        image[last] = 1;
+        match PieHeader::parse(&image) {
+            Err(PieError::ChecksumMismatch { .. }) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
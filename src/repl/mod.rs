@@ -14,6 +14,12 @@ pub struct REPL {
     vm: VM,
 }
 
+impl Default for REPL {
+    fn default() -> Self {
+        REPL::new()
+    }
+}
+
 impl REPL {
     pub fn new() -> REPL {
         REPL {
@@ -49,9 +55,16 @@ impl REPL {
                     let mut f = File::open(Path::new(&filename)).expect("File not found");
                     let mut contents = String::new();
                     f.read_to_string(&mut contents).expect("There was an error reading from the file");
-                    let mut asm = Assembler::new();
-                    if let Ok(mut bytes) = asm.assemble(&contents) {
-                        self.vm.program.append(&mut bytes);
+                    let mut asm = Assembler::with_file_name(tmp);
+                    match asm.assemble(&contents) {
+                        Ok(mut bytes) => {
+                            self.vm.program.append(&mut bytes);
+                        },
+                        Err(errors) => {
+                            for error in errors {
+                                print!("{}", error.render(&contents));
+                            }
+                        },
                     }
                 },
                 ".program" => {
@@ -79,8 +92,16 @@ impl REPL {
                         }
                     };
                     let asm = Assembler::new();
-                    self.vm.program.append(&mut program.to_bytes(&asm.symbols));
-                    self.vm.run_once();
+                    match program.to_bytes(&asm.symbols) {
+                        Ok(mut bytes) => self.vm.program.append(&mut bytes),
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    }
+                    if let Err(e) = self.vm.run_once() {
+                        println!("VM error: {}", e);
+                    }
                 }
             }
         }
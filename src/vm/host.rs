@@ -0,0 +1,61 @@
+use std::io::{self, BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `syscall` service numbers. Arguments are passed in `$0` and `$1`, and
+/// results come back in the same registers:
+///
+/// - `SYS_EXIT` stops the program with the status in `$0`.
+/// - `SYS_PRINT_INT` prints `$0` in decimal.
+/// - `SYS_PRINT_CHAR` prints the code point in `$0`.
+/// - `SYS_READ_INT` reads a line and parses it into `$0`.
+/// - `SYS_READ_LINE` copies a line into the heap at `$0`, writing at most
+///   `$1` bytes, and sets `$1` to the number written.
+/// - `SYS_TIME` sets `$0` to the low 32 bits and `$1` to the high 32 bits
+///   of the seconds since the Unix epoch.
+///
+/// The read services set the equal flag if they read anything.
+pub const SYS_EXIT: u16 = 0;
+pub const SYS_PRINT_INT: u16 = 1;
+pub const SYS_PRINT_CHAR: u16 = 2;
+pub const SYS_READ_INT: u16 = 3;
+pub const SYS_READ_LINE: u16 = 4;
+pub const SYS_TIME: u16 = 5;
+
+/// Everything a program does to the outside world goes through a `Host`:
+/// `prts` output as well as `syscall`. Embedders can swap in their own to
+/// capture output, script input or deny access to the real clock.
+pub trait Host {
+    fn write(&mut self, text: &str);
+    /// Returns the next line of input without its line ending, or `None` at
+    /// the end of input.
+    fn read_line(&mut self) -> Option<String>;
+    /// Seconds since the Unix epoch.
+    fn time(&mut self) -> i64;
+}
+
+/// Uses the process's stdin, stdout and clock.
+#[derive(Debug, Default)]
+pub struct StdHost;
+
+impl Host for StdHost {
+    fn write(&mut self, text: &str) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(text.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()),
+        }
+    }
+
+    fn time(&mut self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() as i64,
+            Err(_) => 0,
+        }
+    }
+}
@@ -0,0 +1,1709 @@
+pub mod host;
+pub mod snapshot;
+pub mod trace;
+pub mod vm_config;
+pub mod vm_errors;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use instruction::{Opcode, NO_REGISTER};
+use pie::{PieError, PieHeader};
+use vm::host::{Host, StdHost};
+use vm::host::{SYS_EXIT, SYS_PRINT_CHAR, SYS_PRINT_INT, SYS_READ_INT, SYS_READ_LINE, SYS_TIME};
+use vm::trace::{TraceEntry, Tracer};
+use vm::vm_config::VmConfig;
+use vm::vm_errors::{ExitReason, VmError};
+
+/// How much of the instruction budget an opcode uses up by default. Opcodes
+/// that allocate, divide or call out to the host cost more.
+pub fn default_cost(opcode: Opcode) -> u64 {
+    match opcode {
+        Opcode::DIV | Opcode::MOD | Opcode::DIVF64 => 4,
+        Opcode::CALL | Opcode::RET | Opcode::PUSH | Opcode::POP => 2,
+        Opcode::ALOC | Opcode::PRTS => 5,
+        Opcode::SYSCALL => 10,
+        _ => 1,
+    }
+}
+
+pub struct VM {
+    pub registers: [i32; 32],
+    pub float_registers: [f64; 32],
+    pc: usize,
+    pub program: Vec<u8>,
+    heap: Vec<u8>,
+    remainder: u32,
+    equal_flag: bool,
+    ro_data: Vec<u8>,
+    stack: Vec<i32>,
+    pub config: VmConfig,
+    instruction_pc: usize,
+    /// Receives everything the program prints and services `syscall`.
+    pub host: Box<dyn Host>,
+    /// What is left of the instruction budget, or `None` for no limit.
+    pub budget: Option<u64>,
+    costs: Vec<u64>,
+    /// Gets an entry for every instruction executed, when set.
+    pub tracer: Option<Box<dyn Tracer>>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> VM {
+        VM::with_config(VmConfig::default())
+    }
+
+    pub fn with_config(config: VmConfig) -> VM {
+        VM {
+            registers: [0; 32],
+            float_registers: [0.0; 32],
+            program: vec![],
+            heap: vec![],
+            pc: 0,
+            remainder: 0,
+            equal_flag: false,
+            ro_data: vec![],
+            stack: vec![],
+            config,
+            instruction_pc: 0,
+            host: Box::new(StdHost),
+            budget: None,
+            costs: (0..=255u8).map(|byte| default_cost(Opcode::from(byte))).collect(),
+            tracer: None,
+        }
+    }
+
+    pub fn add_byte(&mut self, b: u8) {
+        self.program.push(b)
+    }
+
+    pub fn add_bytes(&mut self, mut b: Vec<u8>) {
+        self.program.append(&mut b);
+    }
+
+    pub fn run(&mut self) -> Result<ExitReason, VmError> {
+        self.load()?;
+        self.resume()
+    }
+
+    /// Carries on from the current `pc`, e.g. after `run` returned
+    /// `BudgetExhausted` and more budget has been added.
+    pub fn resume(&mut self) -> Result<ExitReason, VmError> {
+        loop {
+            if let Some(reason) = self.execute_instruction()? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Validates the image in `program` and gets ready to run it from its
+    /// entry point, one `run_once` at a time.
+    pub fn load(&mut self) -> Result<(), VmError> {
+        if self.program.len() > self.config.max_program_size {
+            return Err(VmError::ProgramTooLarge { size: self.program.len(), limit: self.config.max_program_size });
+        }
+        let header = self.verify_header().map_err(|error| VmError::InvalidHeader { error })?;
+        self.ro_data = self.program[header.ro.start()..header.ro.end()].to_vec();
+        self.pc = header.entry_point as usize;
+        Ok(())
+    }
+
+    pub fn run_once(&mut self) -> Result<Option<ExitReason>, VmError> {
+        self.execute_instruction()
+    }
+
+    pub fn add_budget(&mut self, amount: u64) {
+        self.budget = Some(self.budget.unwrap_or(0).saturating_add(amount));
+    }
+
+    pub fn set_cost(&mut self, opcode: Opcode, cost: u64) {
+        self.costs[u8::from(opcode) as usize] = cost;
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn heap(&self) -> &[u8] {
+        &self.heap
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Executes the instruction at `pc`. Returns `Some` once the program has
+    /// finished and `None` if there is more to run.
+    pub fn execute_instruction(&mut self) -> Result<Option<ExitReason>, VmError> {
+        if self.pc >= self.program.len() {
+            return Ok(Some(ExitReason::EndOfProgram));
+        }
+        // An instruction the budget cannot pay for is left unexecuted, so
+        // that `resume` picks up exactly where this stopped.
+        if let Some(remaining) = self.budget {
+            let cost = self.costs[self.program[self.pc] as usize];
+            if cost > remaining {
+                return Ok(Some(ExitReason::BudgetExhausted));
+            }
+            self.budget = Some(remaining - cost);
+        }
+        self.instruction_pc = self.pc;
+        if self.tracer.is_none() {
+            return self.execute_opcode();
+        }
+
+        let end = (self.pc + 4).min(self.program.len());
+        let mut entry = TraceEntry::new(self.pc, &self.program[self.pc..end], &self.ro_data);
+        let registers = self.registers;
+        let float_registers = self.float_registers;
+        let result = self.execute_opcode();
+        entry.record_changes((&registers, &float_registers), (&self.registers, &self.float_registers));
+        if let Some(tracer) = self.tracer.as_mut() {
+            if let Err(e) = tracer.record(&entry) {
+                return Err(VmError::TraceFailed { pc: self.instruction_pc, message: e.to_string() });
+            }
+        }
+        result
+    }
+
+    fn execute_opcode(&mut self) -> Result<Option<ExitReason>, VmError> {
+        match self.decode_opcode() {
+            Opcode::LOAD => {
+                let register = self.next_register()?;
+                let number = self.next_16_bits()?;
+                self.registers[register] = i32::from(number);
+            },
+            Opcode::ADD => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1.wrapping_add(val2);
+            },
+            Opcode::SUB => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1.wrapping_sub(val2);
+            },
+            Opcode::MUL => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1.wrapping_mul(val2);
+            },
+            Opcode::DIV => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                if val2 == 0 {
+                    return Err(VmError::DivideByZero { pc: self.instruction_pc });
+                }
+                self.registers[self.next_register()?] = val1.wrapping_div(val2);
+                self.remainder = val1.wrapping_rem(val2) as u32;
+            },
+            Opcode::HLT => {
+                debug!("HLT encountered at pc {}", self.instruction_pc);
+                return Ok(Some(ExitReason::Halted));
+            },
+            Opcode::JMP => {
+                let target = self.registers[self.next_register()?];
+                self.jump_to(i64::from(target))?;
+            },
+            Opcode::JMPF => {
+                let val = self.registers[self.next_register()?];
+                let target = self.pc as i64 + i64::from(val);
+                self.jump_to(target)?;
+            },
+            Opcode::JMPB => {
+                let val = self.registers[self.next_register()?];
+                let target = self.pc as i64 - i64::from(val);
+                self.jump_to(target)?;
+            },
+            Opcode::EQ => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.set_comparison(val1 == val2)?;
+            },
+            Opcode::NEQ => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.set_comparison(val1 != val2)?;
+            },
+            Opcode::GT => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.set_comparison(val1 > val2)?;
+            },
+            Opcode::LT => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.set_comparison(val1 < val2)?;
+            },
+            Opcode::GTE => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.set_comparison(val1 >= val2)?;
+            },
+            Opcode::LTE => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.set_comparison(val1 <= val2)?;
+            },
+            Opcode::JMPE => {
+                if self.equal_flag {
+                    let target = self.registers[self.next_register()?];
+                    self.jump_to(i64::from(target))?;
+                } else {
+                    self.next_8_bits()?;
+                    self.next_16_bits()?;
+                }
+            },
+            Opcode::ALOC => {
+                let bytes = self.registers[self.next_register()?];
+                let new_end = self.heap.len() as i64 + i64::from(bytes);
+                if new_end < 0 {
+                    return Err(VmError::HeapOutOfBounds { pc: self.instruction_pc, address: new_end });
+                }
+                if new_end > self.config.max_heap_bytes as i64 {
+                    return Err(VmError::OutOfMemory { pc: self.instruction_pc, requested: new_end });
+                }
+                self.heap.resize(new_end as usize, 0);
+                self.next_16_bits()?;
+            },
+            Opcode::INC => {
+                let register = self.next_register()?;
+                self.registers[register] = self.registers[register].wrapping_add(1);
+                self.next_16_bits()?;
+            },
+            Opcode::DEC => {
+                let register = self.next_register()?;
+                self.registers[register] = self.registers[register].wrapping_sub(1);
+                self.next_16_bits()?;
+            },
+            Opcode::DJMPE => {
+                let target = self.next_16_bits()?;
+                self.next_8_bits()?;
+                if self.equal_flag {
+                    self.jump_to(i64::from(target))?;
+                }
+            },
+            Opcode::PRTS => {
+                let starting_offset = self.next_16_bits()? as usize;
+                let length = match self.ro_data.iter().skip(starting_offset).position(|b| *b == 0) {
+                    Some(length) => length,
+                    None => return Err(VmError::RoDataOutOfBounds { pc: self.instruction_pc, offset: starting_offset }),
+                };
+                match std::str::from_utf8(&self.ro_data[starting_offset..starting_offset + length]) {
+                    Ok(s) => self.host.write(s),
+                    Err(e) => warn!("prts at pc {} read a string that is not UTF-8: {}", self.instruction_pc, e),
+                };
+                self.next_8_bits()?;
+            },
+            Opcode::LB => {
+                let register = self.next_register()?;
+                let address = self.heap_address(1)?;
+                self.registers[register] = i32::from(self.heap[address]);
+            },
+            Opcode::LW => {
+                let register = self.next_register()?;
+                let address = self.heap_address(4)?;
+                self.registers[register] = BigEndian::read_i32(&self.heap[address..address + 4]);
+            },
+            Opcode::SB => {
+                let value = self.registers[self.next_register()?];
+                let address = self.heap_address(1)?;
+                self.heap[address] = value as u8;
+            },
+            Opcode::SW => {
+                let value = self.registers[self.next_register()?];
+                let address = self.heap_address(4)?;
+                BigEndian::write_i32(&mut self.heap[address..address + 4], value);
+            },
+            Opcode::CALL => {
+                let target = self.next_16_bits()?;
+                self.next_8_bits()?;
+                let return_address = self.pc as i32;
+                self.push(return_address)?;
+                self.jump_to(i64::from(target))?;
+            },
+            Opcode::RET => {
+                self.next_8_bits()?;
+                self.next_16_bits()?;
+                let return_address = self.pop()?;
+                self.jump_to(i64::from(return_address))?;
+            },
+            Opcode::PUSH => {
+                let value = self.registers[self.next_register()?];
+                self.next_16_bits()?;
+                self.push(value)?;
+            },
+            Opcode::POP => {
+                let register = self.next_register()?;
+                self.next_16_bits()?;
+                self.registers[register] = self.pop()?;
+            },
+            Opcode::JMPNE => {
+                if !self.equal_flag {
+                    let target = self.registers[self.next_register()?];
+                    self.jump_to(i64::from(target))?;
+                } else {
+                    self.next_8_bits()?;
+                    self.next_16_bits()?;
+                }
+            },
+            Opcode::DJMP => {
+                let target = self.next_16_bits()?;
+                self.next_8_bits()?;
+                self.jump_to(i64::from(target))?;
+            },
+            Opcode::DJMPNE => {
+                let target = self.next_16_bits()?;
+                self.next_8_bits()?;
+                if !self.equal_flag {
+                    self.jump_to(i64::from(target))?;
+                }
+            },
+            // The relative jumps count from the start of the next instruction.
+            Opcode::JMPFE => {
+                let offset = self.next_16_bits()?;
+                self.next_8_bits()?;
+                if self.equal_flag {
+                    let target = self.pc as i64 + i64::from(offset);
+                    self.jump_to(target)?;
+                }
+            },
+            Opcode::JMPBE => {
+                let offset = self.next_16_bits()?;
+                self.next_8_bits()?;
+                if self.equal_flag {
+                    let target = self.pc as i64 - i64::from(offset);
+                    self.jump_to(target)?;
+                }
+            },
+            Opcode::AND => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1 & val2;
+            },
+            Opcode::OR => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1 | val2;
+            },
+            Opcode::XOR => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1 ^ val2;
+            },
+            Opcode::NOT => {
+                let val = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = !val;
+                self.next_8_bits()?;
+            },
+            Opcode::SHL => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1.wrapping_shl(val2 as u32);
+            },
+            Opcode::SHR => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = val1.wrapping_shr(val2 as u32);
+            },
+            Opcode::MOD => {
+                let val1 = self.registers[self.next_register()?];
+                let val2 = self.registers[self.next_register()?];
+                if val2 == 0 {
+                    return Err(VmError::DivideByZero { pc: self.instruction_pc });
+                }
+                self.registers[self.next_register()?] = val1.wrapping_rem(val2);
+            },
+            Opcode::LOADF64 => {
+                let register = self.next_register()?;
+                let offset = self.next_16_bits()? as usize;
+                if offset + 8 > self.ro_data.len() {
+                    return Err(VmError::RoDataOutOfBounds { pc: self.instruction_pc, offset });
+                }
+                self.float_registers[register] = BigEndian::read_f64(&self.ro_data[offset..offset + 8]);
+            },
+            Opcode::ADDF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = val1 + val2;
+            },
+            Opcode::SUBF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = val1 - val2;
+            },
+            Opcode::MULF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = val1 * val2;
+            },
+            Opcode::DIVF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = val1 / val2;
+            },
+            Opcode::EQF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.set_comparison(val1 == val2)?;
+            },
+            Opcode::NEQF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.set_comparison(val1 != val2)?;
+            },
+            Opcode::GTF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.set_comparison(val1 > val2)?;
+            },
+            Opcode::GTEF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.set_comparison(val1 >= val2)?;
+            },
+            Opcode::LTF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.set_comparison(val1 < val2)?;
+            },
+            Opcode::LTEF64 => {
+                let val1 = self.float_registers[self.next_register()?];
+                let val2 = self.float_registers[self.next_register()?];
+                self.set_comparison(val1 <= val2)?;
+            },
+            Opcode::ITOF => {
+                let val = self.registers[self.next_register()?];
+                self.float_registers[self.next_register()?] = f64::from(val);
+                self.next_8_bits()?;
+            },
+            Opcode::FTOI => {
+                let val = self.float_registers[self.next_register()?];
+                self.registers[self.next_register()?] = val as i32;
+                self.next_8_bits()?;
+            },
+            // `load` sets the low half of a register and clears the high
+            // half, so `load` followed by `loadhi` materializes any i32.
+            Opcode::LOADHI => {
+                let register = self.next_register()?;
+                let number = self.next_16_bits()?;
+                let low = self.registers[register] as u32 & 0xffff;
+                self.registers[register] = (u32::from(number) << 16 | low) as i32;
+            },
+            Opcode::EXIT => {
+                let code = self.registers[self.next_register()?];
+                self.next_16_bits()?;
+                return Ok(Some(ExitReason::Exit(code)));
+            },
+            Opcode::SYSCALL => {
+                let service = self.next_16_bits()?;
+                self.next_8_bits()?;
+                return self.syscall(service);
+            },
+            Opcode::IGL => {
+                error!("Unrecognized opcode {} at pc {}", self.program[self.instruction_pc], self.instruction_pc);
+                let opcode = self.program[self.instruction_pc];
+                return Err(VmError::IllegalOpcode { pc: self.instruction_pc, opcode });
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_opcode(&mut self) -> Opcode {
+        let opcode = Opcode::from(self.program[self.pc]);
+        self.pc += 1;
+        opcode
+    }
+
+    fn next_8_bits(&mut self) -> Result<u8, VmError> {
+        if self.pc >= self.program.len() {
+            return Err(VmError::PcOutOfBounds { pc: self.pc });
+        }
+        let result = self.program[self.pc];
+        self.pc += 1;
+        Ok(result)
+    }
+
+    fn next_16_bits(&mut self) -> Result<u16, VmError> {
+        if self.pc + 1 >= self.program.len() {
+            return Err(VmError::PcOutOfBounds { pc: self.pc });
+        }
+        let result = ((self.program[self.pc] as u16) << 8) | self.program[self.pc + 1] as u16;
+        self.pc += 2;
+        Ok(result)
+    }
+
+    fn next_register(&mut self) -> Result<usize, VmError> {
+        let register = self.next_8_bits()?;
+        if register as usize >= self.registers.len() {
+            return Err(VmError::InvalidRegister { pc: self.instruction_pc, register });
+        }
+        Ok(register as usize)
+    }
+
+    /// Carries out a `syscall`: `SYS_EXIT`, `SYS_PRINT_INT`,
+    /// `SYS_PRINT_CHAR`, `SYS_READ_INT`, `SYS_READ_LINE` or `SYS_TIME`.
+    fn syscall(&mut self, service: u16) -> Result<Option<ExitReason>, VmError> {
+        match service {
+            SYS_EXIT => return Ok(Some(ExitReason::Exit(self.registers[0]))),
+            SYS_PRINT_INT => {
+                let text = self.registers[0].to_string();
+                self.host.write(&text);
+            },
+            SYS_PRINT_CHAR => {
+                let c = std::char::from_u32(self.registers[0] as u32).unwrap_or(std::char::REPLACEMENT_CHARACTER);
+                self.host.write(c.encode_utf8(&mut [0; 4]));
+            },
+            SYS_READ_INT => {
+                let value = self.host.read_line().and_then(|line| line.trim().parse::<i32>().ok());
+                self.equal_flag = value.is_some();
+                self.registers[0] = value.unwrap_or(0);
+            },
+            SYS_READ_LINE => {
+                let address = i64::from(self.registers[0]);
+                let capacity = i64::from(self.registers[1].max(0));
+                if address < 0 || address + capacity > self.heap.len() as i64 {
+                    return Err(VmError::HeapOutOfBounds { pc: self.instruction_pc, address });
+                }
+                let line = self.host.read_line();
+                self.equal_flag = line.is_some();
+                let line = line.unwrap_or_default();
+                let length = line.len().min(capacity as usize);
+                let start = address as usize;
+                self.heap[start..start + length].copy_from_slice(&line.as_bytes()[..length]);
+                self.registers[1] = length as i32;
+            },
+            SYS_TIME => {
+                let time = self.host.time();
+                self.registers[0] = time as i32;
+                self.registers[1] = (time >> 32) as i32;
+            },
+            _ => return Err(VmError::UnknownSyscall { pc: self.instruction_pc, service }),
+        }
+        Ok(None)
+    }
+
+    /// Sets the equal flag and, unless the destination operand is
+    /// `NO_REGISTER`, stores the result as 0 or 1 in that register.
+    fn set_comparison(&mut self, result: bool) -> Result<(), VmError> {
+        self.equal_flag = result;
+        let register = self.next_8_bits()?;
+        if register != NO_REGISTER {
+            if register as usize >= self.registers.len() {
+                return Err(VmError::InvalidRegister { pc: self.instruction_pc, register });
+            }
+            self.registers[register as usize] = result as i32;
+        }
+        Ok(())
+    }
+
+    /// Reads a base register and an 8-bit offset and returns the heap address
+    /// they point at, checking that `size` bytes are available there.
+    fn heap_address(&mut self, size: usize) -> Result<usize, VmError> {
+        let base = self.registers[self.next_register()?];
+        let offset = self.next_8_bits()?;
+        let address = i64::from(base) + i64::from(offset);
+        if address < 0 || address as usize + size > self.heap.len() {
+            return Err(VmError::HeapOutOfBounds { pc: self.instruction_pc, address });
+        }
+        Ok(address as usize)
+    }
+
+    fn push(&mut self, value: i32) -> Result<(), VmError> {
+        if self.stack.len() >= self.config.max_stack_depth {
+            return Err(VmError::StackOverflow { pc: self.instruction_pc });
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i32, VmError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(VmError::StackUnderflow { pc: self.instruction_pc }),
+        }
+    }
+
+    fn jump_to(&mut self, target: i64) -> Result<(), VmError> {
+        if target < 0 || target as usize > self.program.len() {
+            return Err(VmError::PcOutOfBounds { pc: self.instruction_pc });
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    fn verify_header(&self) -> Result<PieHeader, PieError> {
+        PieHeader::parse(&self.program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::bool_assert_comparison)]
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use assembler::Assembler;
+    use pie::PIE_HEADER_LENGTH;
+    use vm::trace::Operand;
+
+    #[test]
+    fn test_create_vm() {
+        let test_vm = VM::new();
+        assert_eq!(test_vm.registers[0], 0)
+    }
+
+    #[test]
+    fn test_load_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![0, 0, 1, 244];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_add_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![1, 0, 1, 2];
+        test_vm.registers[0] = 5;
+        test_vm.registers[1] = 10;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_sub_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![2, 0, 1, 2];
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 5;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], 5);
+    }
+
+    #[test]
+    fn test_mul_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![3, 0, 1, 2];
+        test_vm.registers[0] = 2;
+        test_vm.registers[1] = 3;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], 6);
+    }
+
+    #[test]
+    fn test_div_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![4, 0, 1, 2];
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 3;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], 3);
+        assert_eq!(test_vm.remainder, 1);
+    }
+
+    #[test]
+    fn test_hlt_opcode() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![5,0,0,0];
+        test_vm.program = test_bytes;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_jmp_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![6, 0, 0, 0];
+        test_vm.registers[0] = 3;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 3);
+    }
+
+    #[test]
+    fn test_jmpf_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![7, 0, 0, 0];
+        vm.registers[0] = 2;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpb_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![8, 0, 0, 0];
+        vm.registers[0] = 2;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 0);
+    }
+
+    #[test]
+    fn test_eq_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![9, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![9, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 20;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_neq_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![10, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![10, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 20;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_gt_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![11, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 20;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![11, 0, 1, 0];
+        vm.registers[0] = 20;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![11, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_lt_opcode() {
+        let op = 12;
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 20;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 1, 0];
+        vm.registers[0] = 20;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_gte_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![13, 0, 1, 0];
+        vm.registers[0] = 20;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![13, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 20;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![13, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_lte_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![14, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 20;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![14, 0, 1, 0];
+        vm.registers[0] = 20;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new();
+        vm.program = vec![14, 0, 1, 0];
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpe_opcode() {
+        let op = 15;
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 0, 0];
+        vm.registers[0] = 0;
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 0);
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 0, 0];
+        vm.registers[0] = 0;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_aloc_opcode() {
+        let op = 17;
+
+        let mut vm = VM::new();
+        vm.registers[0] = 1024;
+        vm.program = vec![op, 0, 0, 0];
+        vm.run_once().unwrap();
+        assert_eq!(vm.heap.len(), 1024);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_inc_opcode() {
+        let op = 18;
+
+        let mut vm = VM::new();
+        vm.registers[0] = 3;
+        vm.program = vec![op, 0, 0, 0];
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[0], 4);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_dec_opcode() {
+        let op = 19;
+
+        let mut vm = VM::new();
+        vm.registers[0] = 3;
+        vm.program = vec![op, 0, 0, 0];
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[0], 2);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_prts_opcode() {
+        let op = 21;
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 0, 0];
+        vm.ro_data.append(&mut vec![72, 101, 108, 108, 111, 0]);
+        vm.run_once().unwrap();
+    }
+
+    #[test]
+    fn test_run_loads_ro_data() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            hello: .asciiz 'Hello'
+            .code
+            prts @hello
+            hlt
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.run().unwrap();
+        assert_eq!(vm.ro_data, b"Hello\0".to_vec());
+        assert_eq!(vm.pc, PIE_HEADER_LENGTH + 6 + 5);
+    }
+
+    #[test]
+    fn test_run_starts_at_entry_point() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            .entry main
+            .code
+            load $0 #7
+            main: load $1 #9
+            hlt
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.registers[1], 9);
+    }
+
+    #[test]
+    fn test_run_rejects_unsupported_version() {
+        let mut asm = Assembler::new();
+        let mut program = asm.assemble(".data\n.code\nload $0 #7\nhlt\n").unwrap();
+        program[4] = 3;
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        let error = PieError::UnsupportedVersion { found: 3, supported: 2 };
+        assert_eq!(vm.run(), Err(VmError::InvalidHeader { error }));
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.pc, 0);
+    }
+
+    #[test]
+    fn test_lb_opcode() {
+        let mut vm = VM::new();
+        vm.heap = vec![0, 0, 200, 0];
+        vm.registers[1] = 1;
+        vm.program = vec![22, 0, 1, 1];
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[0], 200);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_lw_opcode() {
+        let mut vm = VM::new();
+        vm.heap = vec![0, 0, 0, 1, 2, 0];
+        vm.program = vec![23, 0, 1, 1];
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[0], 258);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_sb_opcode() {
+        let mut vm = VM::new();
+        vm.heap = vec![0; 4];
+        vm.registers[0] = 0x1ff;
+        vm.registers[1] = 2;
+        vm.program = vec![24, 0, 1, 1];
+        vm.run_once().unwrap();
+        assert_eq!(vm.heap, vec![0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_sw_opcode() {
+        let mut vm = VM::new();
+        vm.heap = vec![0; 8];
+        vm.registers[0] = -2;
+        vm.program = vec![25, 0, 1, 4, 23, 2, 1, 4];
+        vm.run_once().unwrap();
+        assert_eq!(vm.heap, vec![0, 0, 0, 0, 255, 255, 255, 254]);
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[2], -2);
+    }
+
+    #[test]
+    fn test_heap_access_out_of_bounds_traps() {
+        let mut vm = VM::new();
+        vm.heap = vec![0; 4];
+        vm.program = vec![23, 0, 1, 1];
+        assert_eq!(vm.run_once(), Err(VmError::HeapOutOfBounds { pc: 0, address: 1 }));
+
+        let mut vm = VM::new();
+        vm.heap = vec![0; 4];
+        vm.registers[1] = -8;
+        vm.program = vec![24, 0, 1, 0];
+        assert_eq!(vm.run_once(), Err(VmError::HeapOutOfBounds { pc: 0, address: -8 }));
+    }
+
+    #[test]
+    fn test_call_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![26, 0, 8, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 8);
+        assert_eq!(vm.stack, vec![4]);
+    }
+
+    #[test]
+    fn test_ret_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![27, 0, 0, 0, 5, 0, 0, 0];
+        vm.stack = vec![4];
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 4);
+        assert_eq!(vm.stack.len(), 0);
+
+        let mut vm = VM::new();
+        vm.program = vec![27, 0, 0, 0];
+        assert_eq!(vm.run_once(), Err(VmError::StackUnderflow { pc: 0 }));
+    }
+
+    #[test]
+    fn test_push_pop_opcodes() {
+        let mut vm = VM::new();
+        vm.program = vec![28, 0, 0, 0, 29, 1, 0, 0];
+        vm.registers[0] = 42;
+        vm.run_once().unwrap();
+        assert_eq!(vm.stack, vec![42]);
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[1], 42);
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_stack_overflow_traps() {
+        let mut vm = VM::new();
+        vm.config.max_stack_depth = 2;
+        vm.program = vec![28, 0, 0, 0, 28, 0, 0, 0, 28, 0, 0, 0];
+        vm.run_once().unwrap();
+        vm.run_once().unwrap();
+        assert_eq!(vm.run_once(), Err(VmError::StackOverflow { pc: 8 }));
+    }
+
+    #[test]
+    fn test_call_and_return_from_subroutine() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            .code
+            load $0 #5
+            call @double
+            call @double
+            hlt
+            double: push $1
+            add $0 $0 $1
+            add $1 $1 $0
+            sub $0 $1 $0
+            pop $1
+            ret
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 20);
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_djmpe_opcode() {
+        let op = 20;
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 8, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 8);
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 8, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpne_opcode() {
+        let op = 30;
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 0, 0];
+        vm.registers[0] = 0;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 0);
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 0, 0];
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_djmp_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![31, 0, 8, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 8);
+
+        let mut vm = VM::new();
+        vm.program = vec![31, 1, 0, 0];
+        assert_eq!(vm.run_once(), Err(VmError::PcOutOfBounds { pc: 0 }));
+    }
+
+    #[test]
+    fn test_djmpne_opcode() {
+        let op = 32;
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 8, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 8);
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 8, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpfe_opcode() {
+        let op = 33;
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 4, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 8);
+
+        let mut vm = VM::new();
+        vm.program = vec![op, 0, 4, 0, 5, 0, 0, 0, 5, 0, 0, 0];
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpbe_opcode() {
+        let op = 34;
+
+        let mut vm = VM::new();
+        vm.program = vec![5, 0, 0, 0, op, 0, 8, 0];
+        vm.pc = 4;
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 0);
+
+        let mut vm = VM::new();
+        vm.program = vec![5, 0, 0, 0, op, 0, 8, 0];
+        vm.pc = 4;
+        vm.run_once().unwrap();
+        assert_eq!(vm.pc, 8);
+    }
+
+    #[test]
+    fn test_loop_with_direct_jump() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            .code
+            load $0 #0
+            load $1 #10
+            loop: inc $0
+            neq $0 $1
+            djmpe @loop
+            hlt
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 10);
+    }
+
+    #[test]
+    fn test_comparison_destination_register() {
+        let mut vm = VM::new();
+        vm.program = vec![11, 0, 1, 2, 11, 1, 0, 3];
+        vm.registers[0] = 20;
+        vm.registers[1] = 10;
+        vm.registers[3] = 7;
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[2], 1);
+        assert_eq!(vm.equal_flag, true);
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[3], 0);
+        assert_eq!(vm.equal_flag, false);
+
+        let mut vm = VM::new();
+        vm.program = vec![9, 0, 1, NO_REGISTER];
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.registers[0], 10);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_and_or_xor_opcodes() {
+        let mut vm = VM::new();
+        vm.program = vec![35, 0, 1, 2, 36, 0, 1, 3, 37, 0, 1, 4];
+        vm.registers[0] = 0b1100;
+        vm.registers[1] = 0b1010;
+        vm.run_once().unwrap();
+        vm.run_once().unwrap();
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[2], 0b1000);
+        assert_eq!(vm.registers[3], 0b1110);
+        assert_eq!(vm.registers[4], 0b0110);
+    }
+
+    #[test]
+    fn test_not_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![38, 0, 1, 0];
+        vm.registers[0] = 0;
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[1], -1);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_shift_opcodes() {
+        let mut vm = VM::new();
+        vm.program = vec![39, 0, 1, 2, 40, 3, 1, 4];
+        vm.registers[0] = 3;
+        vm.registers[1] = 4;
+        vm.registers[3] = -64;
+        vm.run_once().unwrap();
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[2], 48);
+        assert_eq!(vm.registers[4], -4);
+    }
+
+    #[test]
+    fn test_mod_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![41, 0, 1, 2, 41, 0, 3, 2];
+        vm.registers[0] = 17;
+        vm.registers[1] = 5;
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[2], 2);
+        assert_eq!(vm.run_once(), Err(VmError::DivideByZero { pc: 4 }));
+    }
+
+    #[test]
+    fn test_loadf64_opcode() {
+        let mut vm = VM::new();
+        vm.ro_data = vec![0, 0x40, 0x02, 0, 0, 0, 0, 0, 0];
+        vm.program = vec![42, 3, 0, 1, 42, 3, 0, 2];
+        vm.run_once().unwrap();
+        assert_eq!(vm.float_registers[3], 2.25);
+        assert_eq!(vm.run_once(), Err(VmError::RoDataOutOfBounds { pc: 4, offset: 2 }));
+    }
+
+    #[test]
+    fn test_float_arithmetic_opcodes() {
+        let mut vm = VM::new();
+        vm.program = vec![43, 0, 1, 2, 44, 0, 1, 3, 45, 0, 1, 4, 46, 0, 1, 5];
+        vm.float_registers[0] = 3.0;
+        vm.float_registers[1] = 1.5;
+        for _ in 0..4 {
+            vm.run_once().unwrap();
+        }
+        assert_eq!(vm.float_registers[2], 4.5);
+        assert_eq!(vm.float_registers[3], 1.5);
+        assert_eq!(vm.float_registers[4], 4.5);
+        assert_eq!(vm.float_registers[5], 2.0);
+    }
+
+    #[test]
+    fn test_float_comparison_opcodes() {
+        let mut vm = VM::new();
+        vm.program = vec![47, 0, 1, NO_REGISTER, 49, 0, 1, 2, 51, 0, 1, 3];
+        vm.float_registers[0] = 2.5;
+        vm.float_registers[1] = 1.0;
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, true);
+        assert_eq!(vm.registers[2], 1);
+        vm.run_once().unwrap();
+        assert_eq!(vm.equal_flag, false);
+        assert_eq!(vm.registers[3], 0);
+    }
+
+    #[test]
+    fn test_float_conversion_opcodes() {
+        let mut vm = VM::new();
+        vm.program = vec![53, 0, 1, 0, 54, 2, 3, 0];
+        vm.registers[0] = -7;
+        vm.float_registers[2] = 9.75;
+        vm.run_once().unwrap();
+        assert_eq!(vm.float_registers[1], -7.0);
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[3], 9);
+        assert_eq!(vm.pc, 8);
+    }
+
+    #[test]
+    fn test_float_program() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            .code
+            loadf64 $0 #1.5
+            loadf64 $1 #0.25
+            load $2 #4
+            itof $2 $2
+            mulf64 $0 $2 $3
+            addf64 $3 $1 $3
+            ftoi $3 $4
+            hlt
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.float_registers[3], 6.25);
+        assert_eq!(vm.registers[4], 6);
+    }
+
+    #[test]
+    fn test_loadhi_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![55, 0, 0, 1];
+        vm.registers[0] = 0x7fff_86a0;
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[0], 100000);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_load_full_width_immediates() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            .code
+            load $0 #0xffff
+            loadhi $0 #-1
+            load $1 #0x86A0
+            loadhi $1 #0b1
+            hlt
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], -1);
+        assert_eq!(vm.registers[1], 100000);
+    }
+
+    #[test]
+    fn test_igl_opcode() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![200,0,0,0];
+        test_vm.program = test_bytes;
+        assert_eq!(test_vm.run_once(), Err(VmError::IllegalOpcode { pc: 0, opcode: 200 }));
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_divide_by_zero_traps() {
+        let mut vm = VM::new();
+        vm.program = vec![4, 0, 1, 2, 4, 0, 1, 2];
+        vm.registers[0] = 10;
+        vm.registers[1] = 5;
+        vm.run_once().unwrap();
+        vm.registers[1] = 0;
+        assert_eq!(vm.run_once(), Err(VmError::DivideByZero { pc: 4 }));
+        assert_eq!(vm.registers[2], 2);
+    }
+
+    #[test]
+    fn test_invalid_register_traps() {
+        let mut vm = VM::new();
+        vm.program = vec![0, 32, 0, 1];
+        assert_eq!(vm.run_once(), Err(VmError::InvalidRegister { pc: 0, register: 32 }));
+    }
+
+    #[test]
+    fn test_truncated_operand_traps() {
+        let mut vm = VM::new();
+        vm.program = vec![0, 0, 1];
+        assert_eq!(vm.run_once(), Err(VmError::PcOutOfBounds { pc: 2 }));
+    }
+
+    #[test]
+    fn test_jmpb_underflow_traps() {
+        let mut vm = VM::new();
+        vm.program = vec![8, 0, 0, 0];
+        vm.registers[0] = 3;
+        assert_eq!(vm.run_once(), Err(VmError::PcOutOfBounds { pc: 0 }));
+    }
+
+    #[test]
+    fn test_prts_without_terminator_traps() {
+        let mut vm = VM::new();
+        vm.program = vec![21, 0, 2, 0];
+        vm.ro_data = vec![72, 105];
+        assert_eq!(vm.run_once(), Err(VmError::RoDataOutOfBounds { pc: 0, offset: 2 }));
+    }
+
+    #[test]
+    fn test_negative_aloc_traps() {
+        let mut vm = VM::new();
+        vm.program = vec![17, 0, 0, 0];
+        vm.registers[0] = -1;
+        assert_eq!(vm.run_once(), Err(VmError::HeapOutOfBounds { pc: 0, address: -1 }));
+    }
+
+    #[test]
+    fn test_run_reaches_end_of_program() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nload $0 #7\n").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(vm.registers[0], 7);
+    }
+
+    #[test]
+    fn test_opcode_exit() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nload $3 #42\nexit $3\nhlt\n").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Exit(42)));
+    }
+
+    struct TestHost {
+        input: Vec<String>,
+        output: Rc<RefCell<String>>,
+    }
+
+    impl Host for TestHost {
+        fn write(&mut self, text: &str) {
+            self.output.borrow_mut().push_str(text);
+        }
+
+        fn read_line(&mut self) -> Option<String> {
+            if self.input.is_empty() { None } else { Some(self.input.remove(0)) }
+        }
+
+        // Past `i32::MAX`, so that it needs both words of `SYS_TIME`.
+        fn time(&mut self) -> i64 {
+            5_000_000_000
+        }
+    }
+
+    fn vm_with_test_host(input: &[&str]) -> (VM, Rc<RefCell<String>>) {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut vm = VM::new();
+        vm.host = Box::new(TestHost {
+            input: input.iter().map(|line| line.to_string()).collect(),
+            output: output.clone(),
+        });
+        (vm, output)
+    }
+
+    #[test]
+    fn test_opcode_syscall() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            .code
+            syscall #3
+            add $0 $0 $0
+            syscall #1
+            load $0 #10
+            syscall #2
+            load $0 #8
+            aloc $0
+            load $0 #0
+            load $1 #4
+            syscall #4
+            lb $2 $0 #3
+            add $1 $5 $4
+            syscall #5
+            add $0 $2 $0
+            syscall #0
+        ").unwrap();
+        let (mut vm, output) = vm_with_test_host(&[" 21 ", "hello"]);
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Exit(705_032_704 + 108)));
+        assert_eq!(*output.borrow(), "42\n");
+        assert_eq!(vm.registers[4], 4);
+        assert_eq!(vm.registers[1], 1);
+        assert_eq!(vm.equal_flag, true);
+    }
+
+    #[test]
+    fn test_syscall_read_at_end_of_input() {
+        let (mut vm, _) = vm_with_test_host(&[]);
+        vm.program = vec![57, 0, 3, 0];
+        vm.registers[0] = 7;
+        vm.equal_flag = true;
+        vm.run_once().unwrap();
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_unknown_syscall_traps() {
+        let (mut vm, _) = vm_with_test_host(&[]);
+        vm.program = vec![57, 0, 99, 0];
+        assert_eq!(vm.run_once(), Err(VmError::UnknownSyscall { pc: 0, service: 99 }));
+        vm.pc = 0;
+        vm.program = vec![57, 0, 4, 0];
+        vm.registers[1] = 1;
+        assert_eq!(vm.run_once(), Err(VmError::HeapOutOfBounds { pc: 0, address: 0 }));
+    }
+
+    #[test]
+    fn test_prts_writes_to_host() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            hello: .asciiz 'Hello, '
+            world: .asciiz 'world'
+            .code
+            prts @hello
+            prts @world
+            hlt
+        ").unwrap();
+        let (mut vm, output) = vm_with_test_host(&[]);
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(*output.borrow(), "Hello, world");
+    }
+
+    #[test]
+    fn test_budget_exhausted_and_resume() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(r"
+            .data
+            .code
+            load $1 #10
+            loop: inc $0
+            eq $0 $1
+            djmpne @loop
+            hlt
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.budget = Some(10);
+        assert_eq!(vm.run(), Ok(ExitReason::BudgetExhausted));
+        assert_eq!(vm.registers[0], 3);
+        assert_eq!(vm.budget, Some(0));
+
+        vm.add_budget(1000);
+        assert_eq!(vm.resume(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 10);
+        assert_eq!(vm.budget, Some(1000 - 7 * 3 - 1));
+    }
+
+    #[test]
+    fn test_infinite_loop_is_stopped_by_budget() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nloop: djmp @loop\n").unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.set_cost(Opcode::DJMP, 5);
+        vm.budget = Some(1_000);
+        assert_eq!(vm.run(), Ok(ExitReason::BudgetExhausted));
+        assert_eq!(vm.pc, PIE_HEADER_LENGTH);
+    }
+
+    #[test]
+    fn test_aloc_beyond_heap_limit_traps() {
+        let mut vm = VM::with_config(VmConfig { max_heap_bytes: 16, ..VmConfig::default() });
+        vm.program = vec![17, 0, 0, 0, 17, 0, 0, 0];
+        vm.registers[0] = 16;
+        vm.run_once().unwrap();
+        assert_eq!(vm.heap.len(), 16);
+        assert_eq!(vm.run_once(), Err(VmError::OutOfMemory { pc: 4, requested: 32 }));
+        assert_eq!(vm.heap.len(), 16);
+
+        let mut vm = VM::new();
+        vm.program = vec![17, 0, 0, 0];
+        vm.registers[0] = i32::MAX;
+        assert_eq!(vm.run_once(), Err(VmError::OutOfMemory { pc: 0, requested: i64::from(i32::MAX) }));
+    }
+
+    #[test]
+    fn test_program_size_limit() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nhlt\n").unwrap();
+        let mut vm = VM::with_config(VmConfig { max_program_size: 64, ..VmConfig::default() });
+        vm.add_bytes(program);
+        assert_eq!(vm.run(), Err(VmError::ProgramTooLarge { size: 68, limit: 64 }));
+    }
+
+    struct TestTracer {
+        entries: Rc<RefCell<Vec<TraceEntry>>>,
+    }
+
+    impl Tracer for TestTracer {
+        fn record(&mut self, entry: &TraceEntry) -> std::io::Result<()> {
+            self.entries.borrow_mut().push(entry.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_traced_immediate_matches_register() {
+        let entries = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::new();
+        vm.program = vec![0, 0, 255, 255];
+        vm.tracer = Some(Box::new(TestTracer { entries: entries.clone() }));
+        vm.run_once().unwrap();
+        let entry = &entries.borrow()[0];
+        assert_eq!(entry.operands[1], Operand::Integer(vm.registers[0]));
+    }
+
+    #[test]
+    fn test_tracing() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nload $0 #7\nloadf64 $1 #2.5\neq $0 $0\ndiv $0 $2 $3\n").unwrap();
+        let entries = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.tracer = Some(Box::new(TestTracer { entries: entries.clone() }));
+        assert_eq!(vm.run(), Err(VmError::DivideByZero { pc: 84 }));
+
+        let entries = entries.borrow();
+        let summary: Vec<String> = entries.iter().map(|e| e.to_json()).collect();
+        assert_eq!(summary, vec![
+            "{\"pc\":72,\"opcode\":\"load\",\"operands\":[\"$0\",\"#7\"],\"registers\":[{\"register\":0,\"old\":0,\"new\":7}],\"float_registers\":[]}",
+            "{\"pc\":76,\"opcode\":\"loadf64\",\"operands\":[\"$1\",\"#2.5\"],\"registers\":[],\"float_registers\":[{\"register\":1,\"old\":0.0,\"new\":2.5}]}",
+            "{\"pc\":80,\"opcode\":\"eq\",\"operands\":[\"$0\",\"$0\"],\"registers\":[],\"float_registers\":[]}",
+            "{\"pc\":84,\"opcode\":\"div\",\"operands\":[\"$0\",\"$2\",\"$3\"],\"registers\":[],\"float_registers\":[]}",
+        ]);
+    }
+}
@@ -0,0 +1,248 @@
+use std::fmt;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use vm::VM;
+
+pub const SNAPSHOT_PREFIX: [u8; 4] = [0x49, 0x52, 0x53, 0x4e];
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion { found: u16, supported: u16 },
+    Truncated,
+    TrailingBytes { count: usize },
+    /// The snapshot holds more heap or program than this VM's `VmConfig`
+    /// allows.
+    ExceedsLimits,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "snapshot does not start with the snapshot magic number"),
+            SnapshotError::UnsupportedVersion { found, supported } => write!(f, "snapshot version {} is not supported (expected {})", found, supported),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::TrailingBytes { count } => write!(f, "snapshot has {} unexpected trailing bytes", count),
+            SnapshotError::ExceedsLimits => write!(f, "snapshot exceeds this VM's memory limits"),
+        }
+    }
+}
+
+// Reads little-endian values off the front of a snapshot.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+        if length > self.bytes.len() {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(LittleEndian::read_u16(self.take(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(LittleEndian::read_u32(self.take(4)?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(LittleEndian::read_u64(self.take(8)?))
+    }
+
+    fn i32(&mut self) -> Result<i32, SnapshotError> {
+        Ok(LittleEndian::read_i32(self.take(4)?))
+    }
+
+    fn f64(&mut self) -> Result<f64, SnapshotError> {
+        Ok(LittleEndian::read_f64(self.take(8)?))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, SnapshotError> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.write_u32::<LittleEndian>(bytes.len() as u32).unwrap();
+    out.extend_from_slice(bytes);
+}
+
+impl VM {
+    /// Serializes everything needed to carry on running the program: the
+    /// registers, `pc`, flags, stack, heap, read-only data, program and the
+    /// remaining budget. The `VmConfig` and host are not included; they
+    /// belong to whichever VM restores the snapshot.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(&SNAPSHOT_PREFIX);
+        out.write_u16::<LittleEndian>(SNAPSHOT_VERSION).unwrap();
+        for register in self.registers.iter() {
+            out.write_i32::<LittleEndian>(*register).unwrap();
+        }
+        for register in self.float_registers.iter() {
+            out.write_f64::<LittleEndian>(*register).unwrap();
+        }
+        out.write_u64::<LittleEndian>(self.pc as u64).unwrap();
+        out.write_u8(self.equal_flag as u8).unwrap();
+        out.write_u32::<LittleEndian>(self.remainder).unwrap();
+        match self.budget {
+            Some(budget) => {
+                out.write_u8(1).unwrap();
+                out.write_u64::<LittleEndian>(budget).unwrap();
+            },
+            None => out.write_u8(0).unwrap(),
+        }
+        out.write_u32::<LittleEndian>(self.stack.len() as u32).unwrap();
+        for value in &self.stack {
+            out.write_i32::<LittleEndian>(*value).unwrap();
+        }
+        write_bytes(&mut out, &self.heap);
+        write_bytes(&mut out, &self.ro_data);
+        write_bytes(&mut out, &self.program);
+        out
+    }
+
+    /// Replaces this VM's state with a snapshot taken by `snapshot`, ready
+    /// for `resume`. Nothing is changed if the snapshot is rejected.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = Reader { bytes: snapshot };
+        if reader.take(4).map_err(|_| SnapshotError::BadMagic)? != SNAPSHOT_PREFIX {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion { found: version, supported: SNAPSHOT_VERSION });
+        }
+
+        let mut registers = [0; 32];
+        for register in registers.iter_mut() {
+            *register = reader.i32()?;
+        }
+        let mut float_registers = [0.0; 32];
+        for register in float_registers.iter_mut() {
+            *register = reader.f64()?;
+        }
+        let pc = reader.u64()? as usize;
+        let equal_flag = reader.u8()? != 0;
+        let remainder = reader.u32()?;
+        let budget = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.u64()?),
+        };
+        let stack_depth = reader.u32()? as usize;
+        if stack_depth > self.config.max_stack_depth {
+            return Err(SnapshotError::ExceedsLimits);
+        }
+        let mut stack = Vec::with_capacity(stack_depth.min(reader.bytes.len() / 4));
+        for _ in 0..stack_depth {
+            stack.push(reader.i32()?);
+        }
+        let heap = reader.bytes()?;
+        let ro_data = reader.bytes()?;
+        let program = reader.bytes()?;
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::TrailingBytes { count: reader.bytes.len() });
+        }
+        if heap.len() > self.config.max_heap_bytes || program.len() > self.config.max_program_size {
+            return Err(SnapshotError::ExceedsLimits);
+        }
+
+        self.registers = registers;
+        self.float_registers = float_registers;
+        self.pc = pc;
+        self.equal_flag = equal_flag;
+        self.remainder = remainder;
+        self.budget = budget;
+        self.stack = stack;
+        self.heap = heap;
+        self.ro_data = ro_data;
+        self.program = program;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use vm::vm_config::VmConfig;
+    use vm::vm_errors::ExitReason;
+
+    fn program() -> Vec<u8> {
+        let mut asm = Assembler::new();
+        asm.assemble(r"
+            .data
+            .code
+            load $1 #20
+            load $2 #4
+            aloc $2
+            loadf64 $0 #0.5
+            loop: inc $0
+            push $0
+            sw $0 $3 #0
+            itof $0 $1
+            addf64 $0 $1 $0
+            eq $0 $1
+            djmpne @loop
+            hlt
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut uninterrupted = VM::new();
+        uninterrupted.add_bytes(program());
+        assert_eq!(uninterrupted.run(), Ok(ExitReason::Halted));
+
+        let mut vm = VM::new();
+        vm.add_bytes(program());
+        vm.budget = Some(40);
+        assert_eq!(vm.run(), Ok(ExitReason::BudgetExhausted));
+        let snapshot = vm.snapshot();
+
+        let mut restored = VM::new();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        restored.budget = None;
+        assert_eq!(restored.resume(), Ok(ExitReason::Halted));
+
+        uninterrupted.budget = None;
+        assert_eq!(restored.snapshot(), uninterrupted.snapshot());
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_snapshots() {
+        let mut vm = VM::new();
+        vm.add_bytes(program());
+        let snapshot = vm.snapshot();
+
+        let mut restored = VM::new();
+        assert_eq!(restored.restore(&[1, 2]), Err(SnapshotError::BadMagic));
+        assert_eq!(restored.restore(&snapshot[..snapshot.len() - 1]), Err(SnapshotError::Truncated));
+
+        let mut extended = snapshot.clone();
+        extended.push(0);
+        assert_eq!(restored.restore(&extended), Err(SnapshotError::TrailingBytes { count: 1 }));
+
+        let mut future = snapshot.clone();
+        future[4] = 2;
+        assert_eq!(restored.restore(&future), Err(SnapshotError::UnsupportedVersion { found: 2, supported: 1 }));
+
+        let mut small = VM::with_config(VmConfig { max_program_size: 16, ..VmConfig::default() });
+        assert_eq!(small.restore(&snapshot), Err(SnapshotError::ExceedsLimits));
+        assert_eq!(small.program.len(), 0);
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+
+use instruction::{Opcode, OperandKind, NO_REGISTER};
+
+pub const TRACE_PREFIX: [u8; 4] = [0x49, 0x52, 0x54, 0x52];
+pub const TRACE_VERSION: u16 = 1;
+
+/// An operand as the VM decoded it, using the opcode's signature.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Register(u8),
+    Integer(i32),
+    /// A `loadf64` constant, read back from the read-only section.
+    Float(f64),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "${}", register),
+            Operand::Integer(value) => write!(f, "#{}", value),
+            Operand::Float(value) => write!(f, "#{:?}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterChange<T> {
+    pub register: usize,
+    pub old: T,
+    pub new: T,
+}
+
+/// One executed instruction. An instruction that traps is still recorded,
+/// with whatever it changed before trapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub pc: usize,
+    /// The raw instruction, which may be shorter than 4 bytes at the end of
+    /// the program.
+    pub bytes: Vec<u8>,
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>,
+    pub registers: Vec<RegisterChange<i32>>,
+    pub float_registers: Vec<RegisterChange<f64>>,
+}
+
+impl TraceEntry {
+    pub fn new(pc: usize, bytes: &[u8], ro_data: &[u8]) -> TraceEntry {
+        let opcode = bytes.first().map(|byte| Opcode::from(*byte)).unwrap_or(Opcode::IGL);
+        TraceEntry {
+            pc,
+            bytes: bytes.to_vec(),
+            opcode,
+            operands: decode_operands(opcode, bytes, ro_data),
+            registers: vec![],
+            float_registers: vec![],
+        }
+    }
+
+    /// Records every register that differs between `before` and `after`.
+    pub fn record_changes(&mut self, before: (&[i32; 32], &[f64; 32]), after: (&[i32; 32], &[f64; 32])) {
+        self.registers = changes(before.0, after.0, |old, new| old != new);
+        self.float_registers = changes(before.1, after.1, |old, new| old.to_bits() != new.to_bits());
+    }
+
+    /// Writes the entry as one line of JSON.
+    pub fn to_json(&self) -> String {
+        let operands: Vec<String> = self.operands.iter().map(|operand| format!("\"{}\"", operand)).collect();
+        let registers: Vec<String> = self.registers.iter()
+            .map(|c| format!("{{\"register\":{},\"old\":{},\"new\":{}}}", c.register, c.old, c.new))
+            .collect();
+        let float_registers: Vec<String> = self.float_registers.iter()
+            .map(|c| format!("{{\"register\":{},\"old\":{},\"new\":{}}}", c.register, json_f64(c.old), json_f64(c.new)))
+            .collect();
+        format!(
+            "{{\"pc\":{},\"opcode\":\"{}\",\"operands\":[{}],\"registers\":[{}],\"float_registers\":[{}]}}",
+            self.pc, self.opcode.mnemonic(), operands.join(","), registers.join(","), float_registers.join(","),
+        )
+    }
+
+    /// Writes the entry in the binary trace format: the pc as a `u32`, a
+    /// length-prefixed copy of the instruction, then a count of changed
+    /// integer registers followed by each one's number, old and new value,
+    /// and the same for float registers. Operands are not written, since they
+    /// can be decoded from the instruction.
+    pub fn write_binary<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_u32::<LittleEndian>(self.pc as u32)?;
+        out.write_u8(self.bytes.len() as u8)?;
+        out.write_all(&self.bytes)?;
+        out.write_u8(self.registers.len() as u8)?;
+        for change in &self.registers {
+            out.write_u8(change.register as u8)?;
+            out.write_i32::<LittleEndian>(change.old)?;
+            out.write_i32::<LittleEndian>(change.new)?;
+        }
+        out.write_u8(self.float_registers.len() as u8)?;
+        for change in &self.float_registers {
+            out.write_u8(change.register as u8)?;
+            out.write_f64::<LittleEndian>(change.old)?;
+            out.write_f64::<LittleEndian>(change.new)?;
+        }
+        Ok(())
+    }
+}
+
+fn decode_operands(opcode: Opcode, bytes: &[u8], ro_data: &[u8]) -> Vec<Operand> {
+    let mut operands = vec![];
+    let mut position = 1;
+    for kind in opcode.signature() {
+        let width = match kind {
+            OperandKind::Integer16 | OperandKind::Address | OperandKind::Float => 2,
+            _ => 1,
+        };
+        if position + width > bytes.len() {
+            break;
+        }
+        match kind {
+            OperandKind::OptionalRegister if bytes[position] == NO_REGISTER => {},
+            OperandKind::Register | OperandKind::OptionalRegister => operands.push(Operand::Register(bytes[position])),
+            OperandKind::Integer8 => operands.push(Operand::Integer(i32::from(bytes[position]))),
+            OperandKind::Integer16 | OperandKind::Address => operands.push(Operand::Integer(i32::from(BigEndian::read_u16(&bytes[position..])))),
+            OperandKind::Float => {
+                let offset = BigEndian::read_u16(&bytes[position..]) as usize;
+                match ro_data.get(offset..offset + 8) {
+                    Some(constant) => operands.push(Operand::Float(BigEndian::read_f64(constant))),
+                    None => operands.push(Operand::Integer(offset as i32)),
+                }
+            },
+        }
+        position += width;
+    }
+    operands
+}
+
+fn changes<T: Copy, F: Fn(T, T) -> bool>(before: &[T; 32], after: &[T; 32], differs: F) -> Vec<RegisterChange<T>> {
+    (0..32)
+        .filter(|register| differs(before[*register], after[*register]))
+        .map(|register| RegisterChange { register, old: before[register], new: after[register] })
+        .collect()
+}
+
+// JSON has no NaN or infinity, so those are written as null.
+fn json_f64(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        "null".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    JsonLines,
+    Binary,
+}
+
+/// Receives an entry for every instruction the VM executes while tracing.
+pub trait Tracer {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()>;
+    /// Called once the program has stopped, to flush anything buffered.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams a trace to a writer, such as a file, in either format. A binary
+/// trace starts with `TRACE_PREFIX` and `TRACE_VERSION`.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    started: bool,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> TraceWriter<W> {
+        TraceWriter { out, format, started: false }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started && self.format == TraceFormat::Binary {
+            self.out.write_all(&TRACE_PREFIX)?;
+            self.out.write_u16::<LittleEndian>(TRACE_VERSION)?;
+        }
+        self.started = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        self.start()?;
+        match self.format {
+            TraceFormat::JsonLines => writeln!(self.out, "{}", entry.to_json()),
+            TraceFormat::Binary => entry.write_binary(&mut self.out),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.start()?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_operands() {
+        let entry = TraceEntry::new(64, &[9, 0, 1, NO_REGISTER], &[]);
+        assert_eq!(entry.operands, vec![Operand::Register(0), Operand::Register(1)]);
+        let entry = TraceEntry::new(64, &[0, 2, 1, 244], &[]);
+        assert_eq!(entry.operands, vec![Operand::Register(2), Operand::Integer(500)]);
+        let mut ro = vec![0; 8];
+        BigEndian::write_f64(&mut ro, 1.5);
+        let entry = TraceEntry::new(64, &[42, 3, 0, 0], &ro);
+        assert_eq!(entry.opcode, Opcode::LOADF64);
+        assert_eq!(entry.operands, vec![Operand::Register(3), Operand::Float(1.5)]);
+        let entry = TraceEntry::new(64, &[0, 2], &[]);
+        assert_eq!(entry.operands, vec![Operand::Register(2)]);
+        let entry = TraceEntry::new(64, &[0, 2, 255, 255], &[]);
+        assert_eq!(entry.operands, vec![Operand::Register(2), Operand::Integer(65535)]);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut entry = TraceEntry::new(64, &[0, 2, 1, 244], &[]);
+        let mut after = [0; 32];
+        after[2] = 500;
+        let mut after_floats = [0.0; 32];
+        after_floats[1] = f64::NAN;
+        entry.record_changes((&[0; 32], &[0.0; 32]), (&after, &after_floats));
+        assert_eq!(
+            entry.to_json(),
+            "{\"pc\":64,\"opcode\":\"load\",\"operands\":[\"$2\",\"#500\"],\
+             \"registers\":[{\"register\":2,\"old\":0,\"new\":500}],\
+             \"float_registers\":[{\"register\":1,\"old\":0.0,\"new\":null}]}"
+        );
+    }
+
+    #[test]
+    fn test_binary_trace() {
+        let mut entry = TraceEntry::new(64, &[0, 2, 1, 244], &[]);
+        let mut after = [0; 32];
+        after[2] = 500;
+        entry.record_changes((&[0; 32], &[0.0; 32]), (&after, &[0.0; 32]));
+        let mut out = vec![];
+        {
+            let mut writer = TraceWriter::new(&mut out, TraceFormat::Binary);
+            writer.record(&entry).unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(out, vec![
+            0x49, 0x52, 0x54, 0x52, 1, 0,
+            64, 0, 0, 0,
+            4, 0, 2, 1, 244,
+            1, 2, 0, 0, 0, 0, 244, 1, 0, 0,
+            0,
+        ]);
+    }
+}
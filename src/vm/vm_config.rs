@@ -0,0 +1,25 @@
+pub const DEFAULT_MAX_HEAP_BYTES: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_PROGRAM_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_STACK_DEPTH: usize = 1024;
+
+/// Resource limits for a `VM`. A program that goes over one traps instead
+/// of taking the host process down with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmConfig {
+    /// The largest the heap can grow to through `aloc`.
+    pub max_heap_bytes: usize,
+    /// The largest image, header included, that `run` will load.
+    pub max_program_size: usize,
+    /// How many values `push` and `call` can put on the stack.
+    pub max_stack_depth: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            max_heap_bytes: DEFAULT_MAX_HEAP_BYTES,
+            max_program_size: DEFAULT_MAX_PROGRAM_SIZE,
+            max_stack_depth: DEFAULT_MAX_STACK_DEPTH,
+        }
+    }
+}
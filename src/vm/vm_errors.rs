@@ -0,0 +1,56 @@
+use std::fmt;
+
+use pie::PieError;
+
+/// Why a program stopped without a trap.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitReason {
+    Halted,
+    EndOfProgram,
+    /// The program ran `exit` with this status.
+    Exit(i32),
+    /// The instruction budget ran out before the program finished. More
+    /// budget can be added and the program resumed.
+    BudgetExhausted,
+}
+
+/// A trap raised by the guest program. `pc` is the offset of the
+/// instruction that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    InvalidHeader { error: PieError },
+    IllegalOpcode { pc: usize, opcode: u8 },
+    InvalidRegister { pc: usize, register: u8 },
+    DivideByZero { pc: usize },
+    PcOutOfBounds { pc: usize },
+    HeapOutOfBounds { pc: usize, address: i64 },
+    RoDataOutOfBounds { pc: usize, offset: usize },
+    StackOverflow { pc: usize },
+    StackUnderflow { pc: usize },
+    UnknownSyscall { pc: usize, service: u16 },
+    OutOfMemory { pc: usize, requested: i64 },
+    ProgramTooLarge { size: usize, limit: usize },
+    /// The tracer could not record an instruction, e.g. because the trace
+    /// file could not be written.
+    TraceFailed { pc: usize, message: String },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::InvalidHeader { error } => write!(f, "invalid program header: {}", error),
+            VmError::IllegalOpcode { pc, opcode } => write!(f, "illegal opcode {} at pc {}", opcode, pc),
+            VmError::InvalidRegister { pc, register } => write!(f, "invalid register ${} at pc {}", register, pc),
+            VmError::DivideByZero { pc } => write!(f, "division by zero at pc {}", pc),
+            VmError::PcOutOfBounds { pc } => write!(f, "program counter out of bounds at pc {}", pc),
+            VmError::HeapOutOfBounds { pc, address } => write!(f, "heap access out of bounds at address {} (pc {})", address, pc),
+            VmError::RoDataOutOfBounds { pc, offset } => write!(f, "read-only data access out of bounds at offset {} (pc {})", offset, pc),
+            VmError::StackOverflow { pc } => write!(f, "stack overflow at pc {}", pc),
+            VmError::StackUnderflow { pc } => write!(f, "stack underflow at pc {}", pc),
+            VmError::UnknownSyscall { pc, service } => write!(f, "unknown syscall {} at pc {}", service, pc),
+            VmError::OutOfMemory { pc, requested } => write!(f, "out of memory growing the heap to {} bytes (pc {})", requested, pc),
+            VmError::ProgramTooLarge { size, limit } => write!(f, "program is {} bytes, more than the limit of {}", size, limit),
+            VmError::TraceFailed { pc, message } => write!(f, "unable to write the trace at pc {}: {}", pc, message),
+        }
+    }
+}